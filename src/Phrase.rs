@@ -1,11 +1,17 @@
 use crate::fraction::Fraction;
-use crate::phrase_element::{Note, PhraseElement};
+use crate::instrument::InstrumentFamily;
+use crate::phrase_element::{Note, PhraseElement, Source};
+use crate::spelling::spell;
 use std::collections::BTreeMap;
 use std::ops::Bound;
 
+/// The position, duration and pitch classes of each element in a phrase.
+pub type PitchClasses = Vec<(Fraction, Fraction, Vec<u8>)>;
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Phrase {
     elements: BTreeMap<Fraction, (PhraseElement, Fraction)>,
+    family: Option<InstrumentFamily>,
 }
 
 impl PartialOrd for Phrase {
@@ -23,17 +29,26 @@ impl Ord for Phrase {
 }
 
 impl Phrase {
-    pub fn min_duration(&self) -> Fraction {
-        *(self
-            .elements
-            .iter()
-            .map(|(_, (_, duration))| duration)
-            .min()
-            .unwrap())
+    pub(crate) fn new(elements: BTreeMap<Fraction, (PhraseElement, Fraction)>) -> Self {
+        Phrase {
+            elements,
+            family: None,
+        }
     }
 
-    pub(crate) fn new(elements: BTreeMap<Fraction, (PhraseElement, Fraction)>) -> Self {
-        Phrase { elements }
+    pub fn source(&self) -> Option<Source> {
+        self.elements
+            .values()
+            .flat_map(|(element, _)| element.notes())
+            .find_map(|note| note.source)
+    }
+
+    pub fn family(&self) -> Option<InstrumentFamily> {
+        self.family
+    }
+
+    pub fn set_family(&mut self, family: Option<InstrumentFamily>) {
+        self.family = family;
     }
 
     pub fn elements(self) -> BTreeMap<Fraction, (PhraseElement, Fraction)> {
@@ -171,6 +186,8 @@ impl Phrase {
     pub fn split(self, split_point: Fraction) -> (Phrase, Phrase) {
         let mut phrase_1 = Phrase::default();
         let mut phrase_2 = Phrase::default();
+        phrase_1.family = self.family;
+        phrase_2.family = self.family;
         for (position, (mut element, length)) in self.elements() {
             if position + length <= split_point {
                 phrase_1.add_element(element, position, length);
@@ -214,15 +231,18 @@ impl Phrase {
                     count += 1;
                 }
                 PhraseElement::Chord(c) => {
-                    total += c.iter().fold(0, |acc, n| acc + n.value()) as usize;
+                    total += c.iter().fold(0usize, |acc, n| acc + n.value() as usize);
                     count += c.len();
                 }
             }
         }
+        if count == 0 {
+            return 0;
+        }
         (total / count) as u8
     }
 
-    pub fn mean_at(&self, position: Fraction) -> Option<(u8, u8)> {
+    pub fn mean_at(&self, position: Fraction) -> Option<(u32, u32)> {
         let (pos, (element, len)) = self.elements.range(..=position).rev().next()?;
         if *pos + *len > position {
             return Some(element.mean());
@@ -246,22 +266,188 @@ impl Phrase {
         None
     }
 
+    pub fn element_at(&self, position: Fraction) -> Option<(Fraction, &PhraseElement)> {
+        let (pos, (element, len)) = self.elements.range(..=position).next_back()?;
+        if *pos + *len > position {
+            return Some((*pos, element));
+        }
+        None
+    }
+
+    pub fn remove_note(&mut self, position: Fraction, note: Note) {
+        if let Some((element, _)) = self.elements.get_mut(&position) {
+            if !element.remove_note(&note) {
+                self.elements.remove(&position);
+            }
+        }
+        // Remove any ties to the removed note.
+        if note.tie.is_stop() {
+            if let Some((_, (previous, _))) = self.elements.range_mut(..position).next_back() {
+                if let Some(tied) = previous.has_start_tie(note) {
+                    tied.remove_start_tie();
+                }
+            }
+        }
+        if note.tie.is_start() {
+            if let Some((_, (next, _))) = self
+                .elements
+                .range_mut((Bound::Excluded(position), Bound::Unbounded))
+                .next()
+            {
+                if let Some(tied) = next.has_stop_tie(note) {
+                    tied.remove_stop_tie();
+                }
+            }
+        }
+    }
+
     pub fn transpose_octaves(&mut self, octaves: i8) {
         for (_, (el, _)) in &mut self.elements {
             el.transpose_octaves(octaves);
         }
     }
 
+    pub fn respell(&mut self, keys: &BTreeMap<Fraction, i8>) {
+        let pitches: Vec<u8> = self.elements.values().map(|(el, _)| el.max()).collect();
+        let mut previous: Vec<Note> = Vec::new();
+        for (i, (position, (el, _))) in self.elements.iter_mut().enumerate() {
+            let key = keys
+                .range(..=*position)
+                .next_back()
+                .map(|(_, key)| *key)
+                .unwrap_or(0);
+            // The melody's direction is taken from the next element, or the previous one at the end of the phrase.
+            let direction = match pitches.get(i + 1) {
+                Some(next) => next.cmp(&pitches[i]),
+                None if i > 0 => pitches[i].cmp(&pitches[i - 1]),
+                None => std::cmp::Ordering::Equal,
+            };
+            for note in el.notes_mut() {
+                // Tied notes keep the spelling of the note they continue.
+                let tied = previous
+                    .iter()
+                    .find(|n| note.tie.is_stop() && n.value() == note.value());
+                let (step, alter, octave) = match tied {
+                    Some(n) => (n.step, n.alter, n.octave),
+                    None => spell(note, key, direction),
+                };
+                note.step = step;
+                note.alter = alter;
+                note.octave = octave;
+            }
+            previous = el.notes().to_vec();
+        }
+    }
+
+    pub fn shift_cross_staff(&mut self, staves: u8) {
+        for (el, _) in self.elements.values_mut() {
+            el.shift_cross_staff(staves);
+        }
+    }
+
+    pub fn offset(&mut self, offset: Fraction) {
+        self.elements = std::mem::take(&mut self.elements)
+            .into_iter()
+            .map(|(position, element)| (position + offset, element))
+            .collect();
+    }
+
+    pub fn cut_ties(&mut self, start: Fraction, end: Fraction) {
+        for (position, (element, length)) in &mut self.elements {
+            for note in element.notes_mut() {
+                if *position == start {
+                    note.remove_stop_tie();
+                }
+                if *position + *length == end {
+                    note.remove_start_tie();
+                }
+            }
+        }
+    }
+
+    pub fn overlaps(&self, other: &Phrase) -> bool {
+        other.elements.iter().any(|(&position, (_, length))| {
+            self.element_at(position).is_some()
+                || self
+                    .elements
+                    .range((
+                        Bound::Excluded(position),
+                        Bound::Excluded(position + *length),
+                    ))
+                    .next()
+                    .is_some()
+        })
+    }
+
+    pub fn rhythm_matches(&self, other: &Phrase) -> bool {
+        other.elements.iter().all(|(&position, (_, length))| {
+            let same_start = match self.element_at(position) {
+                Some((start, _)) => start == position && self.elements[&start].1 == *length,
+                None => true,
+            };
+            same_start
+                && self
+                    .elements
+                    .range((
+                        Bound::Excluded(position),
+                        Bound::Excluded(position + *length),
+                    ))
+                    .next()
+                    .is_none()
+        })
+    }
+
     pub fn num_elements(&self) -> usize {
         self.elements.len()
     }
+
+    pub fn pitch_classes(&self) -> PitchClasses {
+        self.elements
+            .iter()
+            .map(|(position, (element, duration))| {
+                let mut classes: Vec<u8> = element.notes().iter().map(|n| n.value() % 12).collect();
+                classes.sort_unstable();
+                classes.dedup();
+                (*position, *duration, classes)
+            })
+            .collect()
+    }
+
+    pub fn is_octave_of(&self, other: &Phrase) -> bool {
+        if self.elements.len() != other.elements.len() {
+            return false;
+        }
+        let sorted_values = |element: &PhraseElement| {
+            let mut values: Vec<i32> = element.notes().iter().map(|n| n.value() as i32).collect();
+            values.sort_unstable();
+            values.dedup();
+            values
+        };
+        let mut interval = None;
+        for ((position, (element, _)), (other_position, (other_element, _))) in
+            self.elements.iter().zip(other.elements.iter())
+        {
+            let values = sorted_values(element);
+            let other_values = sorted_values(other_element);
+            if position != other_position || values.len() != other_values.len() {
+                return false;
+            }
+            for (value, other_value) in values.iter().zip(other_values.iter()) {
+                let difference = value - other_value;
+                if difference.abs() != 12 || interval.get_or_insert(difference) != &difference {
+                    return false;
+                }
+            }
+        }
+        true
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::fraction::Fraction;
     use crate::phrase::Phrase;
-    use crate::phrase_element::{Note, NoteName, PhraseElement, Tie};
+    use crate::phrase_element::{Note, NoteName, PhraseElement, Source, Tie};
     use std::collections::BTreeMap;
 
     #[test]
@@ -361,4 +547,111 @@ mod tests {
         assert_eq!(phrase.start(), Fraction::zero());
         assert_eq!(phrase.end(), Fraction::new(2, 1));
     }
+
+    #[test]
+    fn phrase_source() {
+        let source = Source {
+            part: 1,
+            measure: 3,
+            voice: 2,
+        };
+        let mut elements = BTreeMap::new();
+        let mut note = Note::new(NoteName::C, 4, 0, Tie::None);
+        note.source = Some(source);
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Note(note), Fraction::new(2, 1)),
+        );
+        let mut phrase = Phrase::new(elements);
+        phrase.transpose_octaves(1);
+        assert_eq!(phrase.source(), Some(source));
+
+        let (split1, split2) = phrase.split(Fraction::new(1, 1));
+        assert_eq!(split1.source(), Some(source));
+        assert_eq!(split2.source(), Some(source));
+
+        let mut merged = Phrase::default();
+        merged.merge(split2);
+        assert_eq!(merged.source(), Some(source));
+    }
+
+    #[test]
+    fn phrase_octave() {
+        let phrase = |octave| {
+            let mut elements = BTreeMap::new();
+            elements.insert(
+                Fraction::zero(),
+                (
+                    PhraseElement::Note(Note::new(NoteName::C, octave, 0, Tie::None)),
+                    Fraction::new(1, 1),
+                ),
+            );
+            elements.insert(
+                Fraction::new(1, 1),
+                (
+                    PhraseElement::Note(Note::new(NoteName::E, octave, 0, Tie::None)),
+                    Fraction::new(1, 1),
+                ),
+            );
+            Phrase::new(elements)
+        };
+
+        assert!(phrase(4).is_octave_of(&phrase(5)));
+        assert!(phrase(4).is_octave_of(&phrase(3)));
+        assert!(!phrase(4).is_octave_of(&phrase(4)));
+        assert!(!phrase(4).is_octave_of(&phrase(6)));
+        assert_eq!(phrase(4).pitch_classes(), phrase(6).pitch_classes());
+    }
+
+    #[test]
+    fn phrase_remove_note() {
+        let mut elements = BTreeMap::new();
+        let c = Note::new(NoteName::C, 4, 0, Tie::Start);
+        let e = Note::new(NoteName::E, 4, 0, Tie::None);
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Chord(vec![c, e]), Fraction::new(1, 1)),
+        );
+        let mut c_stop = c;
+        c_stop.tie = Tie::Stop;
+        elements.insert(
+            Fraction::new(1, 1),
+            (PhraseElement::Note(c_stop), Fraction::new(1, 1)),
+        );
+        let mut phrase = Phrase::new(elements);
+
+        phrase.remove_note(Fraction::zero(), c);
+        assert_eq!(
+            phrase.element_at(Fraction::new(1, 2)),
+            Some((Fraction::zero(), &PhraseElement::Note(e)))
+        );
+        assert_eq!(
+            phrase.element_at(Fraction::new(1, 1)),
+            Some((
+                Fraction::new(1, 1),
+                &PhraseElement::Note(Note::new(NoteName::C, 4, 0, Tie::None))
+            ))
+        );
+
+        phrase.remove_note(Fraction::zero(), e);
+        assert_eq!(phrase.num_elements(), 1);
+        assert_eq!(phrase.element_at(Fraction::zero()), None);
+    }
+
+    #[test]
+    fn chord_mean() {
+        // The notes of a four note chord add up to more than fits in a byte.
+        let chord = [NoteName::C, NoteName::E, NoteName::G, NoteName::C]
+            .iter()
+            .zip([5, 5, 5, 6].iter())
+            .map(|(&name, &octave)| Note::new(name, octave, 0, Tie::None))
+            .collect();
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Chord(chord), Fraction::new(1, 1)),
+        );
+        assert_eq!(Phrase::new(elements).mean(), 65);
+        assert_eq!(Phrase::default().mean(), 0);
+    }
 }
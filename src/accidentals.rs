@@ -0,0 +1,163 @@
+use crate::phrase_element::{Accidental, Note};
+use std::collections::HashMap;
+
+/// The order in which sharps are added to a key signature, as steps numbered from C. Flats are added in the reverse
+/// order.
+const SHARPS: [i32; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+/// Tracks the accidentals in force on a stave through a bar, to decide which notes need an accidental written.
+///
+/// An accidental lasts until the end of the bar, and only applies to notes of the same step in the same octave. A note
+/// tied over from the previous bar is written without an accidental and doesn't carry its alteration on through the
+/// bar. Where a step and octave was altered away from the key in the previous bar, either by an accidental or by a note
+/// tied over the barline, the first note of that step and octave in the bar which follows the key is given a
+/// cautionary accidental.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AccidentalState {
+    /// The key signature, as a number of sharps or flats if negative.
+    fifths: i8,
+    /// The alteration given by an accidental earlier in the bar to each staff position.
+    bar: HashMap<i32, i8>,
+    /// The alteration of each staff position which differed from the key at the end of the previous bar.
+    cautionary: HashMap<i32, i8>,
+    /// The last alteration of each staff position written in the bar.
+    last: HashMap<i32, i8>,
+}
+
+impl AccidentalState {
+    /// Start a new bar, changing the key signature if there is a new one.
+    pub fn start_bar(&mut self, key: Option<i8>) {
+        if let Some(fifths) = key {
+            self.fifths = fifths;
+        }
+        let last: Vec<(i32, i8)> = self.last.drain().collect();
+        self.cautionary = last
+            .into_iter()
+            .filter(|&(position, alter)| alter != self.key_alter(position))
+            .collect();
+        self.bar.clear();
+    }
+
+    /// Get the accidental to write before a note, if it needs one. Notes must be given in the order they are played.
+    pub fn accidental(&mut self, note: &Note) -> Option<Accidental> {
+        let position = note.staff_position();
+        let key_alter = self.key_alter(position);
+        self.last.insert(position, note.alter);
+        if note.tie.is_stop() {
+            if !self.bar.contains_key(&position) && note.alter != key_alter {
+                self.cautionary.insert(position, note.alter);
+            }
+            return None;
+        }
+
+        let in_force = self.bar.get(&position).cloned().unwrap_or(key_alter);
+        let accidental = if note.alter != in_force {
+            Some(Accidental {
+                alter: note.alter,
+                cautionary: false,
+            })
+        } else if !self.bar.contains_key(&position) && self.cautionary.contains_key(&position) {
+            Some(Accidental {
+                alter: note.alter,
+                cautionary: true,
+            })
+        } else {
+            None
+        };
+        self.bar.insert(position, note.alter);
+        self.cautionary.remove(&position);
+        accidental
+    }
+
+    /// Get the alteration the key signature gives to the step of a staff position.
+    fn key_alter(&self, position: i32) -> i8 {
+        let order = SHARPS
+            .iter()
+            .position(|&step| step == position.rem_euclid(7))
+            .unwrap() as i8;
+        if self.fifths > order {
+            1
+        } else if -self.fifths > 6 - order {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accidentals::AccidentalState;
+    use crate::phrase_element::{Accidental, Note, NoteName, Tie};
+
+    fn accidental(
+        state: &mut AccidentalState,
+        name: NoteName,
+        alter: i8,
+        tie: Tie,
+    ) -> Option<(i8, bool)> {
+        state
+            .accidental(&Note::new(name, 4, alter, tie))
+            .map(|Accidental { alter, cautionary }| (alter, cautionary))
+    }
+
+    #[test]
+    fn key_signature() {
+        let mut state = AccidentalState::default();
+        state.start_bar(Some(-2));
+        assert_eq!(accidental(&mut state, NoteName::B, -1, Tie::None), None);
+        assert_eq!(accidental(&mut state, NoteName::E, -1, Tie::None), None);
+        assert_eq!(
+            accidental(&mut state, NoteName::A, -1, Tie::None),
+            Some((-1, false))
+        );
+        assert_eq!(accidental(&mut state, NoteName::A, -1, Tie::None), None);
+        assert_eq!(
+            accidental(&mut state, NoteName::B, 0, Tie::None),
+            Some((0, false))
+        );
+        assert_eq!(
+            accidental(&mut state, NoteName::B, -1, Tie::None),
+            Some((-1, false))
+        );
+
+        // The accidentals are cancelled by the barline, with a cautionary natural on the A.
+        state.start_bar(None);
+        assert_eq!(
+            accidental(&mut state, NoteName::A, 0, Tie::None),
+            Some((0, true))
+        );
+        assert_eq!(accidental(&mut state, NoteName::B, -1, Tie::None), None);
+    }
+
+    #[test]
+    fn tied_notes() {
+        let mut state = AccidentalState::default();
+        state.start_bar(Some(0));
+        assert_eq!(
+            accidental(&mut state, NoteName::F, 1, Tie::Start),
+            Some((1, false))
+        );
+
+        // The tied note has no accidental, so the next F sharp needs one again.
+        state.start_bar(None);
+        assert_eq!(accidental(&mut state, NoteName::F, 1, Tie::Stop), None);
+        assert_eq!(
+            accidental(&mut state, NoteName::F, 1, Tie::None),
+            Some((1, false))
+        );
+
+        // An F natural after the tied F sharp gets a cautionary natural.
+        state.start_bar(None);
+        assert_eq!(
+            accidental(&mut state, NoteName::F, 1, Tie::Start),
+            Some((1, false))
+        );
+        state.start_bar(None);
+        assert_eq!(accidental(&mut state, NoteName::F, 1, Tie::Stop), None);
+        assert_eq!(
+            accidental(&mut state, NoteName::F, 0, Tie::None),
+            Some((0, true))
+        );
+    }
+}
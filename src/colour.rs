@@ -0,0 +1,111 @@
+use crate::score_representation::PartInfo;
+
+/// The colours used for parts which aren't given a colour explicitly.
+const DEFAULT_PALETTE: [&str; 12] = [
+    "#E6194B", "#3CB44B", "#4363D8", "#F58231", "#911EB4", "#42D4F4", "#F032E6", "#9A6324",
+    "#808000", "#469990", "#800000", "#000075",
+];
+
+/// A palette of colours keyed by source part, used to colour output notes.
+///
+/// Each line of a colour file has the form `part = #RRGGBB`, where `part` is either a part id or a part name. Blank
+/// lines and lines starting with `#` are ignored. Parts which aren't listed are given a colour from the default palette.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColourPalette {
+    colours: Vec<(String, String)>,
+}
+
+impl ColourPalette {
+    /// Parse a palette from the text of a colour file.
+    pub fn parse(text: &str) -> Result<ColourPalette, String> {
+        let mut colours = Vec::new();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", line_num + 1, message);
+
+            let (part, colour) = match line.rfind('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => return Err(error("expected `part = #RRGGBB`")),
+            };
+            if part.is_empty() {
+                return Err(error("missing part id or name"));
+            }
+            if !Self::is_colour(colour) {
+                return Err(error("colour must be of the form #RRGGBB or #AARRGGBB"));
+            }
+            colours.push((part.to_string(), colour.to_uppercase()));
+        }
+        Ok(ColourPalette { colours })
+    }
+
+    /// Check if a string is a valid MusicXML colour.
+    fn is_colour(colour: &str) -> bool {
+        colour.starts_with('#')
+            && (colour.len() == 7 || colour.len() == 9)
+            && colour[1..].chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    /// Get the colour for a part given its index in the part list, matching on the part id first and then the part name.
+    pub fn colour(&self, part_index: usize, part: &PartInfo) -> &str {
+        self.colours
+            .iter()
+            .find(|(p, _)| *p == part.id)
+            .or_else(|| {
+                self.colours
+                    .iter()
+                    .find(|(p, _)| p.eq_ignore_ascii_case(&part.name))
+            })
+            .map(|(_, colour)| colour.as_str())
+            .unwrap_or(DEFAULT_PALETTE[part_index % DEFAULT_PALETTE.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::colour::{ColourPalette, DEFAULT_PALETTE};
+    use crate::score_representation::PartInfo;
+
+    #[test]
+    fn palette_parse() {
+        let palette = ColourPalette::parse("# brass\nP1 = #ff0000\nHorn = #00FF00\n").unwrap();
+        assert_eq!(
+            palette.colours,
+            vec![
+                ("P1".to_string(), "#FF0000".to_string()),
+                ("Horn".to_string(), "#00FF00".to_string()),
+            ]
+        );
+
+        assert!(ColourPalette::parse("P1 = red").is_err());
+        assert!(ColourPalette::parse("P1 = #FF00").is_err());
+        assert!(ColourPalette::parse("P1 #FF0000").is_err());
+    }
+
+    #[test]
+    fn palette_colour() {
+        let palette = ColourPalette::parse("P1 = #FF0000\nhorn = #00FF00").unwrap();
+        let part = PartInfo {
+            id: "P1".to_string(),
+            name: "Flute".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(palette.colour(0, &part), "#FF0000");
+
+        let part = PartInfo {
+            id: "P2".to_string(),
+            name: "Horn".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(palette.colour(1, &part), "#00FF00");
+
+        let part = PartInfo {
+            id: "P3".to_string(),
+            name: "Viola".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(palette.colour(2, &part), DEFAULT_PALETTE[2]);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::fraction::Fraction;
+use crate::score_representation::DropReason;
+
+/// The staves considered for a phrase, each with a score where lower is better.
+pub type Candidates = Vec<(usize, u32)>;
+
+/// Defines what was done with a phrase.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// The phrase was placed on a stave.
+    Placed { stave: usize },
+    /// The phrase was moved from one stave to another.
+    Moved {
+        from: usize,
+        to: usize,
+        reason: MoveReason,
+    },
+    /// The phrase was transposed by a number of octaves to fit the hand.
+    Transposed { stave: usize, octaves: i8 },
+    /// A note of the phrase was removed from a stave.
+    Removed { stave: usize, reason: DropReason },
+}
+
+/// The reason a phrase was moved to another stave.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MoveReason {
+    /// The phrase was further from the other notes on its stave than the hand can stretch.
+    Stretch,
+    /// There were more notes sounding at once on its stave than the hand has fingers.
+    Fingers,
+}
+
+impl MoveReason {
+    /// Get a description of the reason.
+    pub fn description(self) -> &'static str {
+        match self {
+            MoveReason::Stretch => "out of reach of the other notes on its stave",
+            MoveReason::Fingers => "too many notes at once on its stave",
+        }
+    }
+}
+
+/// A decision made about a phrase while reducing the score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// The stage of the reduction which made the decision, such as `distribute_staves`.
+    pub stage: &'static str,
+    /// A description of the phrase.
+    pub phrase: String,
+    /// The position the decision was made at.
+    pub position: Fraction,
+    /// The staves considered. When distributing phrases the score is the distance in semitones from the pitch the stave
+    /// is centred on, and when fitting phrases to the hand it's the stretch the stave would need.
+    pub candidates: Candidates,
+    pub action: Action,
+}
+
+/// A log of every decision made while reducing the score.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DecisionLog {
+    pub decisions: Vec<Decision>,
+    /// The bar positions are measured from, if only an excerpt of the score was reduced.
+    pub first_bar: Option<u32>,
+}
+
+impl DecisionLog {
+    /// Record a decision.
+    pub fn record(
+        &mut self,
+        stage: &'static str,
+        phrase: String,
+        position: Fraction,
+        candidates: Candidates,
+        action: Action,
+    ) {
+        self.decisions.push(Decision {
+            stage,
+            phrase,
+            position,
+            candidates,
+            action,
+        });
+    }
+
+    /// Write the log as readable text, with a line for each decision.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(first_bar) = self.first_bar {
+            text.push_str(&format!(
+                "Positions are measured from the start of bar {}\n",
+                first_bar
+            ));
+        }
+        for decision in &self.decisions {
+            let action = match &decision.action {
+                Action::Placed { stave } => format!("placed on stave {}", stave + 1),
+                Action::Moved { from, to, reason } => format!(
+                    "moved from stave {} to stave {}, {}",
+                    from + 1,
+                    to + 1,
+                    reason.description()
+                ),
+                Action::Transposed { stave, octaves } => format!(
+                    "transposed {} {} octave{} on stave {}",
+                    if *octaves < 0 { "down" } else { "up" },
+                    octaves.abs(),
+                    if octaves.abs() == 1 { "" } else { "s" },
+                    stave + 1
+                ),
+                Action::Removed { stave, reason } => {
+                    format!("removed from stave {}, {}", stave + 1, reason.description())
+                }
+            };
+            let candidates = decision
+                .candidates
+                .iter()
+                .map(|(stave, score)| format!("stave {} = {}", stave + 1, score))
+                .collect::<Vec<_>>()
+                .join(", ");
+            text.push_str(&format!(
+                "{}, position {}: {} {}; candidates: {}\n",
+                decision.stage, decision.position, decision.phrase, action, candidates
+            ));
+        }
+        text
+    }
+
+    /// Write the log as a JSON object holding the bar positions are measured from, which is null unless only an excerpt
+    /// was reduced, and an array with an object for each decision. Staves are numbered from 1.
+    pub fn to_json(&self) -> String {
+        let first_bar = match self.first_bar {
+            Some(first_bar) => first_bar.to_string(),
+            None => "null".to_string(),
+        };
+        let mut json = format!("{{\n  \"first_bar\": {},\n  \"decisions\": [\n", first_bar);
+        for (i, decision) in self.decisions.iter().enumerate() {
+            let action = match &decision.action {
+                Action::Placed { stave } => {
+                    format!("\"action\": \"placed\", \"stave\": {}", stave + 1)
+                }
+                Action::Moved { from, to, reason } => format!(
+                    "\"action\": \"moved\", \"from\": {}, \"to\": {}, \"reason\": {}",
+                    from + 1,
+                    to + 1,
+                    json_string(reason.description())
+                ),
+                Action::Transposed { stave, octaves } => format!(
+                    "\"action\": \"transposed\", \"stave\": {}, \"octaves\": {}",
+                    stave + 1,
+                    octaves
+                ),
+                Action::Removed { stave, reason } => format!(
+                    "\"action\": \"removed\", \"stave\": {}, \"reason\": {}",
+                    stave + 1,
+                    json_string(reason.description())
+                ),
+            };
+            let candidates = decision
+                .candidates
+                .iter()
+                .map(|(stave, score)| format!("{{\"stave\": {}, \"score\": {}}}", stave + 1, score))
+                .collect::<Vec<_>>()
+                .join(", ");
+            json.push_str(&format!(
+                "    {{\"stage\": {}, \"phrase\": {}, \"position\": {}, \"candidates\": [{}], {}}}",
+                json_string(decision.stage),
+                json_string(&decision.phrase),
+                json_string(&decision.position.to_string()),
+                candidates,
+                action
+            ));
+            if i + 1 < self.decisions.len() {
+                json.push(',');
+            }
+            json.push('\n');
+        }
+        json.push_str("  ]\n}\n");
+        json
+    }
+}
+
+/// Quote and escape a string for JSON.
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::explain::{json_string, Action, DecisionLog, MoveReason};
+    use crate::fraction::Fraction;
+
+    fn log() -> DecisionLog {
+        let mut log = DecisionLog::default();
+        log.record(
+            "distribute_staves",
+            "Oboe (P1), bar 1, voice 1, starting C5".to_string(),
+            Fraction::zero(),
+            vec![(0, 3), (1, 12)],
+            Action::Placed { stave: 0 },
+        );
+        log.record(
+            "adjust_octaves",
+            "Oboe (P1), bar 1, voice 1, starting C5".to_string(),
+            Fraction::new(3, 2),
+            vec![(0, 19)],
+            Action::Transposed {
+                stave: 0,
+                octaves: -1,
+            },
+        );
+        log.record(
+            "adjust_octaves",
+            "Horn (P2), bar 1, voice 1, starting G3".to_string(),
+            Fraction::new(3, 2),
+            vec![(0, 16), (1, 7)],
+            Action::Moved {
+                from: 0,
+                to: 1,
+                reason: MoveReason::Fingers,
+            },
+        );
+        log
+    }
+
+    #[test]
+    fn log_text() {
+        assert_eq!(
+            log().to_text(),
+            "distribute_staves, position 0: Oboe (P1), bar 1, voice 1, starting C5 placed on stave 1; candidates: stave 1 = 3, stave 2 = 12\n\
+             adjust_octaves, position 3/2: Oboe (P1), bar 1, voice 1, starting C5 transposed down 1 octave on stave 1; candidates: stave 1 = 19\n\
+             adjust_octaves, position 3/2: Horn (P2), bar 1, voice 1, starting G3 moved from stave 1 to stave 2, too many notes at once on its stave; candidates: stave 1 = 16, stave 2 = 7\n"
+        );
+    }
+
+    #[test]
+    fn log_json() {
+        let json = log().to_json();
+        assert!(json.starts_with("{\n  \"first_bar\": null,\n  \"decisions\": [\n    {\"stage\": \"distribute_staves\", \"phrase\": \"Oboe (P1), bar 1, voice 1, starting C5\", \"position\": \"0\", \"candidates\": [{\"stave\": 1, \"score\": 3}, {\"stave\": 2, \"score\": 12}], \"action\": \"placed\", \"stave\": 1},\n"));
+        assert!(json.contains("\"action\": \"transposed\", \"stave\": 1, \"octaves\": -1}"));
+        assert!(json.ends_with("\"action\": \"moved\", \"from\": 1, \"to\": 2, \"reason\": \"too many notes at once on its stave\"}\n  ]\n}\n"));
+
+        // The first bar of an excerpt is given once for the whole log.
+        let mut log = log();
+        log.first_bar = Some(30);
+        let json = log.to_json();
+        assert!(json.starts_with("{\n  \"first_bar\": 30,\n"));
+        assert_eq!(json.matches("first_bar").count(), 1);
+        assert_eq!(json_string("a \"b\"\\"), "\"a \\\"b\\\"\\\\\"");
+    }
+}
@@ -40,6 +40,14 @@ impl Fraction {
         Fraction::gcd(b % a, a)
     }
 
+    /// Find the least common multiple of two numbers.
+    pub fn lcm(a: i32, b: i32) -> i32 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        (a / Fraction::gcd(a, b) * b).abs()
+    }
+
     /// Balance a fraction.
     pub fn balance(numerator: i32, denominator: i32) -> (i32, i32) {
         // Make sure negative sign is on top
@@ -204,6 +212,17 @@ impl std::cmp::Ord for Fraction {
     }
 }
 
+impl Display for Fraction {
+    /// Display a fraction as a whole number if possible, otherwise as numerator/denominator.
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
 impl Default for Fraction {
     /// The default fraction is zero.
     fn default() -> Self {
@@ -225,6 +244,13 @@ mod tests {
         assert_eq!(Fraction::new(1, 2), fraction);
     }
 
+    #[test]
+    fn fraction_lcm() {
+        assert_eq!(Fraction::lcm(4, 6), 12);
+        assert_eq!(Fraction::lcm(2, 8), 8);
+        assert_eq!(Fraction::lcm(1, 3), 3);
+    }
+
     #[test]
     fn fraction_add() {
         let fraction1 = Fraction::new(1, 1);
@@ -270,6 +296,12 @@ mod tests {
         assert_eq!(Fraction::new(1, 3), fraction1 * fraction2);
     }
 
+    #[test]
+    fn fraction_display() {
+        assert_eq!(Fraction::new(4, 2).to_string(), "2");
+        assert_eq!(Fraction::new(3, 6).to_string(), "1/2");
+    }
+
     #[test]
     fn fraction_divide() {
         let fraction1 = Fraction::new(1, 1);
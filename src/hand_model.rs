@@ -0,0 +1,112 @@
+/// Describes what can be played by a single hand.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HandModel {
+    /// The largest interval the hand can stretch, in semitones.
+    pub span: u32,
+    /// The largest number of notes the hand can play at once.
+    pub fingers: usize,
+}
+
+impl HandModel {
+    /// Create a hand model.
+    pub fn new(span: u32, fingers: usize) -> Self {
+        HandModel { span, fingers }
+    }
+
+    /// Check whether a set of pitches played together fits under the hand.
+    pub fn can_play(&self, pitches: &[u8]) -> bool {
+        let mut pitches = pitches.to_vec();
+        pitches.sort_unstable();
+        pitches.dedup();
+        match (pitches.first(), pitches.last()) {
+            (Some(&min), Some(&max)) => {
+                pitches.len() <= self.fingers && (max - min) as u32 <= self.span
+            }
+            _ => true,
+        }
+    }
+
+    /// Rank notes sounding together by importance, returning their indices with the most important first.
+    ///
+    /// The outer voices are the most important, with the top note ranked above the bass unless bass_first is set.
+    /// Notes which start at this point come before notes held over from earlier, and notes whose pitch class is
+    /// doubled elsewhere in the sonority come last. The importance of each note is scaled by the weight of its part.
+    pub fn rank(pitches: &[u8], onsets: &[bool], weights: &[f64], bass_first: bool) -> Vec<usize> {
+        let max = pitches.iter().max().cloned().unwrap_or_default();
+        let min = pitches.iter().min().cloned().unwrap_or_default();
+        let importance = |i: usize| {
+            let pitch = pitches[i];
+            let mut importance = 0;
+            if pitch == max {
+                importance += if bass_first { 3 } else { 4 };
+            }
+            if pitch == min {
+                importance += if bass_first { 4 } else { 3 };
+            }
+            if onsets[i] {
+                importance += 2;
+            }
+            if pitches
+                .iter()
+                .enumerate()
+                .all(|(j, p)| j == i || p % 12 != pitch % 12)
+            {
+                importance += 1;
+            }
+            f64::from(importance) * weights[i]
+        };
+        let mut ranking: Vec<usize> = (0..pitches.len()).collect();
+        ranking.sort_by(|&a, &b| {
+            importance(b)
+                .partial_cmp(&importance(a))
+                .unwrap()
+                .then(pitches[b].cmp(&pitches[a]))
+        });
+        ranking
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hand_model::HandModel;
+
+    #[test]
+    fn hand_can_play() {
+        let hand = HandModel::new(12, 4);
+        assert!(hand.can_play(&[48, 52, 55, 60]));
+        assert!(hand.can_play(&[48, 48, 52, 55, 60]));
+        assert!(!hand.can_play(&[48, 52, 55, 58, 60]));
+        assert!(!hand.can_play(&[48, 61]));
+        assert!(hand.can_play(&[]));
+    }
+
+    #[test]
+    fn hand_rank() {
+        let pitches = [48, 52, 55, 60];
+        let onsets = [true, true, false, true];
+        let weights = [1.0; 4];
+        assert_eq!(
+            HandModel::rank(&pitches, &onsets, &weights, false),
+            vec![3, 0, 1, 2]
+        );
+        assert_eq!(
+            HandModel::rank(&pitches, &onsets, &weights, true),
+            vec![0, 3, 1, 2]
+        );
+
+        // The doubled pitch class in the middle is the least important.
+        let pitches = [48, 55, 60, 64];
+        let onsets = [true, true, true, true];
+        assert_eq!(
+            HandModel::rank(&pitches, &onsets, &weights, false),
+            vec![3, 0, 1, 2]
+        );
+
+        // A heavily weighted inner part outranks the outer voices.
+        let weights = [1.0, 3.0, 1.0, 0.5];
+        assert_eq!(
+            HandModel::rank(&pitches, &onsets, &weights, false),
+            vec![1, 0, 3, 2]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::fraction::Fraction;
+use crate::output_score::BarNumbers;
+use crate::phrase::Phrase;
+use std::collections::BTreeMap;
+
+/// Bars whose similarity falls below this are considered to have changed harmony.
+const CHANGED_SIMILARITY: f64 = 0.9;
+
+/// The harmonic content of a beat.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Beat {
+    /// The length of time each pitch class sounds for within the beat.
+    chroma: [f64; 12],
+    /// The lowest note sounding in the beat.
+    bass: Option<u8>,
+}
+
+impl Beat {
+    /// Check whether a pitch class sounds in the beat.
+    fn has(&self, pitch_class: u8) -> bool {
+        self.chroma[pitch_class as usize] > 0.0
+    }
+
+    /// Get the pitch class of the third above the bass, if there is one.
+    fn third(&self) -> Option<u8> {
+        let bass = self.bass? % 12;
+        [(bass + 4) % 12, (bass + 3) % 12]
+            .iter()
+            .cloned()
+            .find(|&third| self.has(third))
+    }
+
+    /// Get the cosine similarity of the chroma of two beats, or None if both are silent.
+    fn similarity(&self, other: &Beat) -> Option<f64> {
+        let dot: f64 = self
+            .chroma
+            .iter()
+            .zip(&other.chroma)
+            .map(|(a, b)| a * b)
+            .sum();
+        let norm = |chroma: &[f64; 12]| chroma.iter().map(|a| a * a).sum::<f64>().sqrt();
+        match (norm(&self.chroma), norm(&other.chroma)) {
+            (a, b) if a == 0.0 && b == 0.0 => None,
+            (a, b) if a == 0.0 || b == 0.0 => Some(0.0),
+            (a, b) => Some(dot / (a * b)),
+        }
+    }
+}
+
+/// How well the harmony of a bar is kept by the reduction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarSimilarity {
+    pub bar: usize,
+    /// The mean cosine similarity of the chroma of each beat in the bar.
+    pub similarity: f64,
+    /// Whether the third above the bass is missing from any beat of the reduction.
+    pub lost_third: bool,
+    /// Whether the bass of any beat of the reduction is a different pitch class to the source score.
+    pub missing_bass: bool,
+}
+
+impl BarSimilarity {
+    /// Check whether the harmony of the bar has clearly changed.
+    pub fn changed(&self) -> bool {
+        self.similarity < CHANGED_SIMILARITY || self.lost_third || self.missing_bass
+    }
+}
+
+/// Compares the harmony of the source score with the reduction beat by beat, using the pitch classes sounding in each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HarmonicFidelity {
+    /// The similarity of every bar in which something sounds.
+    pub bars: Vec<BarSimilarity>,
+    /// The mean similarity of all the bars.
+    pub overall: f64,
+}
+
+impl HarmonicFidelity {
+    /// Compare the phrases of the source score with the phrases of the reduction.
+    pub fn new<'a>(
+        original: impl Iterator<Item = &'a Phrase> + Clone,
+        reduced: impl Iterator<Item = &'a Phrase> + Clone,
+        times: &BTreeMap<Fraction, (u8, u8)>,
+    ) -> Self {
+        let end = original
+            .clone()
+            .chain(reduced.clone())
+            .filter(|p| p.num_elements() > 0)
+            .map(|p| p.end())
+            .max()
+            .unwrap_or_else(Fraction::zero);
+        let beats = Self::beats(end, times);
+        let original_beats = Self::chroma(original, &beats);
+        let reduced_beats = Self::chroma(reduced, &beats);
+
+        let bar_numbers = BarNumbers::new(times);
+        let mut bars: BTreeMap<usize, (Vec<f64>, bool, bool)> = BTreeMap::new();
+        for (i, (start, _)) in beats.iter().enumerate() {
+            let (original, reduced) = (&original_beats[i], &reduced_beats[i]);
+            let similarity = match original.similarity(reduced) {
+                Some(similarity) => similarity,
+                None => continue,
+            };
+            let bar = bars
+                .entry(bar_numbers.get_bar_number(*start) + 1)
+                .or_default();
+            bar.0.push(similarity);
+            if let Some(third) = original.third() {
+                bar.1 |= !reduced.has(third);
+            }
+            if let Some(bass) = original.bass {
+                bar.2 |= reduced.bass.map(|b| b % 12) != Some(bass % 12);
+            }
+        }
+
+        let bars: Vec<BarSimilarity> = bars
+            .into_iter()
+            .map(
+                |(bar, (similarities, lost_third, missing_bass))| BarSimilarity {
+                    bar,
+                    similarity: similarities.iter().sum::<f64>() / similarities.len() as f64,
+                    lost_third,
+                    missing_bass,
+                },
+            )
+            .collect();
+        let overall = if bars.is_empty() {
+            1.0
+        } else {
+            bars.iter().map(|b| b.similarity).sum::<f64>() / bars.len() as f64
+        };
+        HarmonicFidelity { bars, overall }
+    }
+
+    /// Split the score into beats up to an end position, following the time signatures.
+    fn beats(end: Fraction, times: &BTreeMap<Fraction, (u8, u8)>) -> Vec<(Fraction, Fraction)> {
+        let mut beats = Vec::new();
+        let mut position = Fraction::zero();
+        while position < end {
+            let (_, (_, beat_type)) = times
+                .range(..=position)
+                .next_back()
+                .unwrap_or((&position, &(4, 4)));
+            let length = Fraction::new(4, *beat_type as i32);
+            beats.push((position, position + length));
+            position += length;
+        }
+        beats
+    }
+
+    /// Get the harmonic content of each beat in a set of phrases.
+    fn chroma<'a>(
+        phrases: impl Iterator<Item = &'a Phrase>,
+        beats: &[(Fraction, Fraction)],
+    ) -> Vec<Beat> {
+        let mut chroma = vec![Beat::default(); beats.len()];
+        for (&start, (element, length)) in phrases.flat_map(|p| p.elements_ref()) {
+            let end = start + *length;
+            let first = beats.partition_point(|(_, beat_end)| *beat_end <= start);
+            for (beat, &(beat_start, beat_end)) in beats.iter().enumerate().skip(first) {
+                if beat_start >= end {
+                    break;
+                }
+                let overlap = end.min(beat_end) - start.max(beat_start);
+                let overlap = overlap.numerator() as f64 / overlap.denominator() as f64;
+                for note in element.notes() {
+                    let value = note.value();
+                    chroma[beat].chroma[(value % 12) as usize] += overlap;
+                    chroma[beat].bass = Some(chroma[beat].bass.map_or(value, |b| b.min(value)));
+                }
+            }
+        }
+        chroma
+    }
+
+    /// Write the similarity of each bar as readable text, flagging the bars where the harmony has changed.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Harmonic similarity: {:.3} overall\n", self.overall);
+        let changed: Vec<String> = self
+            .bars
+            .iter()
+            .filter(|b| b.changed())
+            .map(|b| b.bar.to_string())
+            .collect();
+        if !changed.is_empty() {
+            text.push_str(&format!(
+                "Bars with changed harmony: {}\n",
+                changed.join(", ")
+            ));
+        }
+        for bar in &self.bars {
+            let mut flags = Vec::new();
+            if bar.lost_third {
+                flags.push("lost third");
+            }
+            if bar.missing_bass {
+                flags.push("missing bass");
+            }
+            if bar.similarity < CHANGED_SIMILARITY {
+                flags.push("changed harmony");
+            }
+            text.push_str(&format!("Bar {}: {:.3}", bar.bar, bar.similarity));
+            if !flags.is_empty() {
+                text.push_str(&format!(" ({})", flags.join(", ")));
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fraction::Fraction;
+    use crate::harmony::HarmonicFidelity;
+    use crate::phrase::Phrase;
+    use crate::phrase_element::{Note, NoteName, PhraseElement, Tie};
+    use std::collections::BTreeMap;
+
+    fn chord(notes: &[(NoteName, u8)], length: i32) -> Phrase {
+        let mut elements = BTreeMap::new();
+        let chord = notes
+            .iter()
+            .map(|&(name, octave)| Note::new(name, octave, 0, Tie::None))
+            .collect();
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Chord(chord), Fraction::new(length, 1)),
+        );
+        Phrase::new(elements)
+    }
+
+    #[test]
+    fn harmonic_fidelity() {
+        let mut times = BTreeMap::new();
+        times.insert(Fraction::zero(), (2, 4));
+        let original = [chord(
+            &[(NoteName::C, 3), (NoteName::E, 4), (NoteName::G, 4)],
+            4,
+        )];
+
+        let fidelity = HarmonicFidelity::new(original.iter(), original.iter(), &times);
+        assert_eq!(fidelity.bars.len(), 2);
+        assert!((fidelity.overall - 1.0).abs() < 1e-9);
+        assert!(!fidelity.bars[0].changed());
+
+        // Losing the third changes the harmony of both bars.
+        let reduced = [chord(&[(NoteName::C, 3), (NoteName::G, 4)], 4)];
+        let fidelity = HarmonicFidelity::new(original.iter(), reduced.iter(), &times);
+        assert!(fidelity.bars[0].lost_third);
+        assert!(!fidelity.bars[0].missing_bass);
+        assert!(fidelity.bars[0].similarity < 0.9);
+
+        // Losing the bass only in the second bar.
+        let mut reduced = vec![chord(
+            &[(NoteName::C, 3), (NoteName::E, 4), (NoteName::G, 4)],
+            2,
+        )];
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::new(2, 1),
+            (
+                PhraseElement::Chord(vec![
+                    Note::new(NoteName::E, 4, 0, Tie::None),
+                    Note::new(NoteName::G, 4, 0, Tie::None),
+                ]),
+                Fraction::new(2, 1),
+            ),
+        );
+        reduced.push(Phrase::new(elements));
+        let fidelity = HarmonicFidelity::new(original.iter(), reduced.iter(), &times);
+        assert!(!fidelity.bars[0].changed());
+        assert!(fidelity.bars[1].missing_bass);
+        assert!(fidelity.bars[1].changed());
+    }
+}
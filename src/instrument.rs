@@ -0,0 +1,136 @@
+/// Defines the instrument families of the orchestra.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash, PartialOrd, Ord)]
+pub enum InstrumentFamily {
+    Woodwind,
+    Brass,
+    Strings,
+    Percussion,
+    Keyboard,
+}
+
+impl InstrumentFamily {
+    /// Get an instrument family from a MusicXML instrument sound, such as `wind.flutes.flute`.
+    pub fn from_sound(sound: &str) -> Option<InstrumentFamily> {
+        let category = sound.split('.').next()?;
+        Some(match category {
+            "wind" => InstrumentFamily::Woodwind,
+            "brass" => InstrumentFamily::Brass,
+            "strings" | "pluck" => InstrumentFamily::Strings,
+            "drum" | "metal" | "wood" | "pitched-percussion" | "effect" => {
+                InstrumentFamily::Percussion
+            }
+            "keyboard" => InstrumentFamily::Keyboard,
+            _ => return None,
+        })
+    }
+
+    /// Guess an instrument family from the name of an instrument, part or group.
+    pub fn from_name(name: &str) -> Option<InstrumentFamily> {
+        let name = name.to_lowercase();
+        let families = [
+            (
+                InstrumentFamily::Woodwind,
+                &[
+                    "woodwind",
+                    "flute",
+                    "piccolo",
+                    "oboe",
+                    "english horn",
+                    "cor anglais",
+                    "clarinet",
+                    "bassoon",
+                    "saxophone",
+                    "recorder",
+                ][..],
+            ),
+            (
+                InstrumentFamily::Brass,
+                &[
+                    "brass",
+                    "horn",
+                    "trumpet",
+                    "cornet",
+                    "trombone",
+                    "tuba",
+                    "euphonium",
+                ][..],
+            ),
+            (
+                InstrumentFamily::Percussion,
+                &[
+                    "percussion",
+                    "timpani",
+                    "drum",
+                    "cymbal",
+                    "triangle",
+                    "glockenspiel",
+                    "xylophone",
+                    "marimba",
+                    "vibraphone",
+                    "bells",
+                ][..],
+            ),
+            (
+                InstrumentFamily::Keyboard,
+                &["keyboard", "piano", "organ", "harpsichord", "celesta"][..],
+            ),
+            (
+                InstrumentFamily::Strings,
+                &[
+                    "string", "violin", "viola", "cello", "bass", "harp", "guitar",
+                ][..],
+            ),
+        ];
+        families
+            .iter()
+            .find(|(_, keywords)| keywords.iter().any(|k| name.contains(k)))
+            .map(|(family, _)| *family)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::InstrumentFamily;
+
+    #[test]
+    fn family_from_sound() {
+        assert_eq!(
+            InstrumentFamily::from_sound("wind.flutes.flute"),
+            Some(InstrumentFamily::Woodwind)
+        );
+        assert_eq!(
+            InstrumentFamily::from_sound("brass.trombone.alto"),
+            Some(InstrumentFamily::Brass)
+        );
+        assert_eq!(
+            InstrumentFamily::from_sound("drum.timpani"),
+            Some(InstrumentFamily::Percussion)
+        );
+        assert_eq!(InstrumentFamily::from_sound("voice.alto"), None);
+    }
+
+    #[test]
+    fn family_from_name() {
+        assert_eq!(
+            InstrumentFamily::from_name("Clarinet (B Flat) 1"),
+            Some(InstrumentFamily::Woodwind)
+        );
+        assert_eq!(
+            InstrumentFamily::from_name("English Horn"),
+            Some(InstrumentFamily::Woodwind)
+        );
+        assert_eq!(
+            InstrumentFamily::from_name("Horn (F) 2"),
+            Some(InstrumentFamily::Brass)
+        );
+        assert_eq!(
+            InstrumentFamily::from_name("Bass Trombone"),
+            Some(InstrumentFamily::Brass)
+        );
+        assert_eq!(
+            InstrumentFamily::from_name("Double Bass"),
+            Some(InstrumentFamily::Strings)
+        );
+        assert_eq!(InstrumentFamily::from_name("Soprano"), None);
+    }
+}
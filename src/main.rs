@@ -1,9 +1,22 @@
+mod accidentals;
+mod colour;
+mod explain;
 mod fraction;
+mod hand_model;
+mod harmony;
+mod instrument;
+mod meter;
 mod music_xml;
+mod optimize;
 mod output_score;
+mod part_filter;
+mod part_mapping;
 mod phrase;
 mod phrase_element;
+mod reduction;
+mod report;
 mod score_representation;
+mod spelling;
 
 use clap::{App, Arg};
 use std::fs;
@@ -33,25 +46,70 @@ fn main() {
                 .short("a")
                 .help("Use the Merge By Average transformation instead of Distribute Staves"),
         )
+        .arg(
+            Arg::with_name("part-map")
+                .short("p")
+                .help("A file mapping part ids or names to output staves, with optional octave shifts")
+                .conflicts_with("merge-by-average")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("group-families")
+                .short("f")
+                .help("Keep phrases from the same instrument family which start together on the same stave")
+                .conflicts_with_all(&["merge-by-average", "part-map"]),
+        )
+        .arg(
+            Arg::with_name("remove-doublings")
+                .short("d")
+                .help("Remove phrases which double another phrase at the unison or octave"),
+        )
+        .arg(
+            Arg::with_name("keep-octaves")
+                .short("k")
+                .help("Keep octave doublings removed by -d as octaves in the output")
+                .requires("remove-doublings"),
+        )
         .arg(
             Arg::with_name("no-merge")
                 .short("m")
                 .help("Don't merge phrases together in the output")
                 .conflicts_with("merge-by-average"),
         )
+        .arg(
+            Arg::with_name("independent-voices")
+                .long("independent-voices")
+                .help("Merge phrases into up to two voices on each stave, with stems up and down, keeping different rhythms apart and only merging matching rhythms into chords")
+                .conflicts_with("no-merge"),
+        )
         .arg(
             Arg::with_name("no-adjust-octaves")
                 .short("n")
                 .help("Don't adjust octaves to ensure the piece fits within a handspan"),
         )
+        .arg(
+            Arg::with_name("repair-chords")
+                .short("r")
+                .help("Give the outer notes of chords wider than the handspan to a neighbouring stave after merging"),
+        )
+        .arg(
+            Arg::with_name("ossia")
+                .long("ossia")
+                .help("Write notes which had to be dropped to fit the hand to an extra stave above the others"),
+        )
         .arg(
             Arg::with_name("handspan")
                 .short("h")
                 .help("The maximum stretch permissible within a stave in semitones")
-                .conflicts_with("no-adjust-octaves")
                 .takes_value(true)
                 .default_value("12"),
         )
+        .arg(
+            Arg::with_name("fingers")
+                .long("fingers")
+                .help("The maximum number of notes which can be played at once within a stave. There is no limit unless this is given")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("staves")
                 .short("s")
@@ -59,6 +117,91 @@ fn main() {
                 .takes_value(true)
                 .default_value("2"),
         )
+        .arg(
+            Arg::with_name("sources")
+                .long("sources")
+                .help("Write a listing of where each output note came from in the source score to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("explain")
+                .long("explain")
+                .help("Write a log explaining where each phrase was placed, moved, transposed or removed to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("explain-format")
+                .long("explain-format")
+                .help("The format of the explanation log")
+                .possible_values(&["text", "json"])
+                .takes_value(true)
+                .default_value("text"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .help("Write a report comparing the notes, onsets, harmony and playability of the reduction with the source score to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("colour")
+                .short("c")
+                .help("Colour each output note by the part it came from"),
+        )
+        .arg(
+            Arg::with_name("colour-map")
+                .long("colour-map")
+                .help("A file mapping part ids or names to note colours, implies -c")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cues")
+                .long("cues")
+                .help("Label entries on each stave with the abbreviated names of the parts they came from"),
+        )
+        .arg(
+            Arg::with_name("voices")
+                .long("voices")
+                .help("The most voices to write on each stave, from 2 to 4. Phrases which don't overlap share a voice, and any more are merged into chords")
+                .takes_value(true)
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("optimize")
+                .long("optimize")
+                .help("Try every combination of staves, handspan, phrase length and strategy, ignoring -s, -h, -l, -a and -f, and write out the one with the best report score along with a table of all of them")
+                .conflicts_with("part-map"),
+        )
+        .arg(
+            Arg::with_name("bars")
+                .short("b")
+                .help("Only reduce a range of bars, such as 12-16, keeping their original bar numbers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .help("Only reduce the parts whose id or name matches a pattern, where * matches anything and ? matches any character. Can be given more than once")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .help("Leave out the parts whose id or name matches a pattern. Can be given more than once")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("weight")
+                .long("weight")
+                .help("Give the parts matching a pattern a weight, such as \"Oboe*=2\", to make their notes more or less likely to be kept than the default of 1 when removing doublings (-d) and when choosing which notes to move or drop because the hand can't play them. It doesn't affect which stave a part is placed on. Can be given more than once")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name("max-phrase-length")
                 .short("l")
@@ -72,6 +215,7 @@ fn main() {
     let input_filename = matches.value_of("input").unwrap();
     let output_filename = matches.value_of("output").unwrap();
     let merge_by_average = matches.is_present("merge-by-average");
+    let group_families = matches.is_present("group-families");
     let no_merge = matches.is_present("no-merge");
     let no_adjust_octaves = matches.is_present("no-adjust-octaves");
 
@@ -92,6 +236,56 @@ fn main() {
             process::exit(1)
         });
 
+    let bars = matches.value_of("bars").map(|bars| {
+        let mut values = bars.splitn(2, '-').map(|b| b.trim().parse::<u32>());
+        match (values.next(), values.next()) {
+            (Some(Ok(first)), None) => (first, first),
+            (Some(Ok(first)), Some(Ok(last))) if first <= last => (first, last),
+            _ => {
+                println!("Bars must be a bar number or a range of bar numbers, such as 12-16");
+                process::exit(1)
+            }
+        }
+    });
+
+    let mut part_filter = part_filter::PartFilter::default();
+    for pattern in matches.values_of("include").into_iter().flatten() {
+        part_filter.include(pattern);
+    }
+    for pattern in matches.values_of("exclude").into_iter().flatten() {
+        part_filter.exclude(pattern);
+    }
+    for weight in matches.values_of("weight").into_iter().flatten() {
+        part_filter.add_weight(weight).unwrap_or_else(|err| {
+            println!("Could not parse weight {}: {}", weight, err);
+            process::exit(1)
+        });
+    }
+
+    let handspan: u32 = matches
+        .value_of("handspan")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_err| {
+            println!("Handspan must be an integer");
+            process::exit(1)
+        });
+    if handspan < 12 {
+        println!("Handspan must be greater than or equal to 12 semitones");
+        process::exit(1)
+    }
+    let fingers: usize = matches.value_of("fingers").map_or(usize::MAX, |fingers| {
+        fingers.parse().unwrap_or_else(|_err| {
+            println!("Number of fingers must be an integer");
+            process::exit(1)
+        })
+    });
+    if fingers == 0 {
+        println!("Number of fingers must be at least 1");
+        process::exit(1)
+    }
+    let hand = hand_model::HandModel::new(handspan, fingers);
+
     let mut input_file = fs::File::open(input_filename).unwrap_or_else(|err| {
         println!(
             "Could not open file {}, failed with error: {}",
@@ -117,36 +311,162 @@ fn main() {
         process::exit(1)
     });
 
+    let part_mapping = matches.value_of("part-map").map(|mapping_filename| {
+        let text = fs::read_to_string(mapping_filename).unwrap_or_else(|err| {
+            println!(
+                "Could not open file {}, failed with error: {}",
+                mapping_filename, err
+            );
+            process::exit(1)
+        });
+        part_mapping::PartMapping::parse(&text, staves).unwrap_or_else(|err| {
+            println!(
+                "Could not parse part mapping {}, failed with error: {}",
+                mapping_filename, err
+            );
+            process::exit(1)
+        })
+    });
+
+    let max_voices: usize = matches
+        .value_of("voices")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_err| {
+            println!("Number of voices must be an integer");
+            process::exit(1)
+        });
+    if !(2..=4).contains(&max_voices) {
+        println!("Number of voices must be from 2 to 4");
+        process::exit(1)
+    }
+
+    let mut output_options = output_score::OutputOptions {
+        cue_labels: matches.is_present("cues"),
+        max_voices,
+        ..Default::default()
+    };
+    if let Some(colour_filename) = matches.value_of("colour-map") {
+        let text = fs::read_to_string(colour_filename).unwrap_or_else(|err| {
+            println!(
+                "Could not open file {}, failed with error: {}",
+                colour_filename, err
+            );
+            process::exit(1)
+        });
+        output_options.colours = Some(colour::ColourPalette::parse(&text).unwrap_or_else(|err| {
+            println!(
+                "Could not parse colour map {}, failed with error: {}",
+                colour_filename, err
+            );
+            process::exit(1)
+        }));
+    } else if matches.is_present("colour") {
+        output_options.colours = Some(colour::ColourPalette::default());
+    }
+
     let mut parser = score_representation::ScoreParser::new(doc);
+    parser.set_filter(part_filter);
+    if let Some((first, last)) = bars {
+        parser.set_bars(first, last);
+        output_options.first_bar = Some(first);
+    }
     let phrase_list = parser.parse_score(phrase_len);
-    let stave_list = if merge_by_average {
-        phrase_list.merge_by_average(staves)
-    } else {
-        let mut stave_list = phrase_list.distribute_staves(staves);
-        if !no_adjust_octaves {
-            let handspan: u32 = matches
-                .value_of("handspan")
-                .unwrap()
-                .parse()
-                .unwrap_or_else(|_err| {
-                    println!("Handspan must be an integer");
-                    process::exit(1)
-                });
-            if handspan < 12 {
-                println!("Handspan must be greater than or equal to 12 semitones");
-                process::exit(1)
-            }
-            stave_list.adjust_octaves(handspan);
+    if phrase_list.phrases().is_empty() {
+        match bars {
+            Some((first, last)) => println!("There are no notes in bars {} to {}", first, last),
+            None => println!("There are no notes in the selected parts"),
         }
-        stave_list
+        process::exit(1)
+    }
+    let original = if matches.is_present("report") {
+        phrase_list.phrases().to_vec()
+    } else {
+        Vec::new()
     };
-
-    let stave_list = if no_merge {
-        stave_list
+    let reduction = reduction::Reduction {
+        staves,
+        hand,
+        strategy: if merge_by_average {
+            reduction::Strategy::MergeByAverage
+        } else if group_families {
+            reduction::Strategy::GroupFamilies
+        } else {
+            reduction::Strategy::DistributeStaves
+        },
+        part_mapping,
+        remove_doublings: matches.is_present("remove-doublings"),
+        keep_octaves: matches.is_present("keep-octaves"),
+        adjust_octaves: !no_adjust_octaves,
+        merge: !no_merge,
+        merge_voices: matches.is_present("independent-voices"),
+        repair_chords: matches.is_present("repair-chords"),
+    };
+    let (reduction, mut stave_list) = if matches.is_present("optimize") {
+        let (reduction, stave_list, table) = optimize::optimize(&mut parser, &reduction);
+        print!("{}", table);
+        (reduction, stave_list)
     } else {
-        stave_list.merge()
+        let stave_list = reduction.reduce(phrase_list);
+        (reduction, stave_list)
     };
-    let output = output_score::OutputScore::new(stave_list);
+    if !stave_list.dropped.is_empty() {
+        if matches.is_present("ossia") {
+            println!(
+                "{} notes could not be played and were moved to an ossia stave, use --sources to list them",
+                stave_list.dropped.len()
+            );
+        } else {
+            println!(
+                "{} notes could not be played and were dropped, use --sources to list them or --ossia to keep them",
+                stave_list.dropped.len()
+            );
+        }
+    }
+    // Positions in an excerpt are measured from its first bar.
+    stave_list.log.first_bar = bars.map(|(first, _)| first);
+    if let Some(sources_filename) = matches.value_of("sources") {
+        fs::write(sources_filename, stave_list.source_listing()).unwrap_or_else(|err| {
+            println!(
+                "Could not write to sources file {}, failed with error: {}",
+                sources_filename, err
+            );
+            process::exit(1)
+        });
+    }
+
+    if let Some(explain_filename) = matches.value_of("explain") {
+        let log = if matches.value_of("explain-format") == Some("json") {
+            stave_list.log.to_json()
+        } else {
+            stave_list.log.to_text()
+        };
+        fs::write(explain_filename, log).unwrap_or_else(|err| {
+            println!(
+                "Could not write to explanation file {}, failed with error: {}",
+                explain_filename, err
+            );
+            process::exit(1)
+        });
+    }
+    if let Some(report_filename) = matches.value_of("report") {
+        let mut report = report::Report::new(&original, &stave_list, &reduction.hand);
+        if let Some((first, _)) = bars {
+            report.set_first_bar(first as usize);
+        }
+        fs::write(report_filename, report.to_text()).unwrap_or_else(|err| {
+            println!(
+                "Could not write to report file {}, failed with error: {}",
+                report_filename, err
+            );
+            process::exit(1)
+        });
+    }
+    if matches.is_present("ossia") {
+        stave_list.add_ossia_stave();
+    }
+
+    let output = output_score::OutputScore::new(stave_list, &output_options);
     let mut output_file = fs::File::create(output_filename).unwrap_or_else(|err| {
         println!(
             "Could not create output file {}, failed with error: {}",
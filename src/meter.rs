@@ -0,0 +1,445 @@
+use crate::fraction::Fraction;
+use crate::phrase_element::{Beam, NoteLength, NoteType};
+
+/// The beat hierarchy of a time signature, used to split notes and rests so that they show the beats of the bar.
+///
+/// A bar is divided into groups of beats, each group into beats, and each beat into halves, or thirds for the dotted
+/// beats of compound meters. A note which starts off a division and runs past its end is split at the end of the
+/// division, while a note which starts on a division may run on through the following ones as long as it can be
+/// written as a single note. Within a beat, any note which can be written as a single note is kept whole.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Meter {
+    /// The lengths of the groups of beats making up a bar.
+    groups: Vec<Fraction>,
+    /// The length of a beat.
+    beat: Fraction,
+}
+
+impl Meter {
+    /// Get the beat hierarchy of a time signature. Meters with a multiple of three beats from six upwards are
+    /// compound, and meters of four, five and seven beats are grouped into twos and threes.
+    pub fn new((beats, beat_type): (u8, u8)) -> Self {
+        let unit = Fraction::new(4, beat_type as i32);
+        let (beat, count) = if beats >= 6 && beats % 3 == 0 {
+            (unit * Fraction::new(3, 1), beats / 3)
+        } else {
+            (unit, beats)
+        };
+        let groups = match count {
+            4 => vec![2, 2],
+            5 => vec![3, 2],
+            7 => vec![2, 2, 3],
+            _ => vec![1; count as usize],
+        };
+        Meter {
+            groups: groups
+                .into_iter()
+                .map(|n| beat * Fraction::new(n, 1))
+                .collect(),
+            beat,
+        }
+    }
+
+    /// Get the length of a bar.
+    pub fn bar_length(&self) -> Fraction {
+        self.groups
+            .iter()
+            .fold(Fraction::zero(), |total, group| total + *group)
+    }
+
+    /// Split a note or rest starting at an offset from the start of the bar into the lengths it should be written as,
+    /// which are tied together if there are more than one.
+    pub fn split(&self, offset: Fraction, length: Fraction) -> Vec<NoteLength> {
+        if length <= Fraction::zero() {
+            return Vec::new();
+        }
+        let end = offset + length;
+        if offset < Fraction::zero() || end > self.bar_length() {
+            return Self::split_greedy(length);
+        }
+        self.split_division(offset, end, Fraction::zero(), self.bar_length(), true)
+    }
+
+    /// Split the part of a note from start to end lying within a division of the bar.
+    fn split_division(
+        &self,
+        start: Fraction,
+        end: Fraction,
+        division_start: Fraction,
+        division_length: Fraction,
+        is_bar: bool,
+    ) -> Vec<NoteLength> {
+        let whole_division = start == division_start && end == division_start + division_length;
+        if whole_division || division_length <= self.beat {
+            if let Some(length) = NoteLength::from_fraction(end - start) {
+                return vec![length];
+            }
+        }
+        if division_length < NoteType::N1024th.get_value() {
+            return Self::split_greedy(end - start);
+        }
+
+        let children = self.subdivide(division_start, division_length, is_bar);
+        let mut lengths = Vec::new();
+        let mut position = start;
+        let mut child = children
+            .iter()
+            .rposition(|(child_start, _)| *child_start <= start)
+            .unwrap();
+        while position < end {
+            let (child_start, child_length) = children[child];
+            let child_end = child_start + child_length;
+            if end <= child_end || position != child_start {
+                // The note ends within this child, or starts off its beat, so it is split at the end of the child.
+                let piece_end = end.min(child_end);
+                lengths.extend(self.split_division(
+                    position,
+                    piece_end,
+                    child_start,
+                    child_length,
+                    false,
+                ));
+                position = piece_end;
+                child += 1;
+                continue;
+            }
+
+            // The note starts on this child and runs past it, so find the longest single note it can continue as.
+            if let Some(length) = NoteLength::from_fraction(end - position) {
+                lengths.push(length);
+                break;
+            }
+            let longest = (child..children.len())
+                .rev()
+                .filter(|&i| children[i].0 + children[i].1 < end)
+                .find_map(|i| {
+                    NoteLength::from_fraction(children[i].0 + children[i].1 - position)
+                        .map(|length| (i, length))
+                });
+            match longest {
+                Some((last, length)) => {
+                    lengths.push(length);
+                    position += length.get_value();
+                    child = last + 1;
+                }
+                None => {
+                    lengths.extend(self.split_division(
+                        position,
+                        child_end,
+                        child_start,
+                        child_length,
+                        false,
+                    ));
+                    position = child_end;
+                    child += 1;
+                }
+            }
+        }
+        lengths
+    }
+
+    /// Get the start and length of each child of a division.
+    fn subdivide(
+        &self,
+        start: Fraction,
+        length: Fraction,
+        is_bar: bool,
+    ) -> Vec<(Fraction, Fraction)> {
+        let lengths = if is_bar && self.groups.len() > 1 {
+            self.groups.clone()
+        } else if length > self.beat && (length % self.beat).is_zero() {
+            vec![self.beat; (length / self.beat).to_whole() as usize]
+        } else if length.numerator() % 3 == 0 {
+            vec![length / Fraction::new(3, 1); 3]
+        } else {
+            vec![length / Fraction::new(2, 1); 2]
+        };
+        let mut position = start;
+        lengths
+            .into_iter()
+            .map(|length| {
+                let child = (position, length);
+                position += length;
+                child
+            })
+            .collect()
+    }
+
+    /// Get the lengths of the groups of notes beamed together in a bar. Notes are beamed by the beat, or by the groups
+    /// of beats in meters such as 5/8 whose beats are shorter than a quarter, or across the whole bar in meters such as
+    /// 3/8. Beats longer than a dotted quarter are beamed by their halves, or thirds if they are dotted.
+    fn beam_groups(&self) -> Vec<Fraction> {
+        let compound = self.beat.numerator() % 3 == 0;
+        let group = if !compound && self.beat < Fraction::new(1, 1) {
+            if self.groups.iter().any(|group| *group > self.beat) {
+                return self.groups.clone();
+            }
+            self.bar_length()
+        } else if self.beat > Fraction::new(3, 2) {
+            self.beat / Fraction::new(if compound { 3 } else { 2 }, 1)
+        } else {
+            self.beat
+        };
+        vec![group; (self.bar_length() / group).to_whole() as usize]
+    }
+
+    /// Get the beams of each of a run of notes in a voice, given as their offset from the start of the bar and their
+    /// length, with no rests between them. The first beam of each note is the primary beam, followed by the secondary
+    /// beams of shorter notes. Notes are beamed together while they follow on from each other within a beam group.
+    pub fn beams(&self, notes: &[(Fraction, NoteLength)]) -> Vec<Vec<Beam>> {
+        let mut group_starts = Vec::new();
+        let mut position = Fraction::zero();
+        for group in self.beam_groups() {
+            group_starts.push(position);
+            position += group;
+        }
+        let group_of = |offset: Fraction| group_starts.iter().rposition(|start| *start <= offset);
+
+        let mut beams = vec![Vec::new(); notes.len()];
+        let mut run: Vec<usize> = Vec::new();
+        for i in 0..=notes.len() {
+            let joins_run = i < notes.len()
+                && run.last().is_some_and(|&last| {
+                    let (last_offset, last_length) = notes[last];
+                    last_offset + last_length.get_value() == notes[i].0
+                        && group_of(last_offset) == group_of(notes[i].0)
+                });
+            if joins_run && notes[i].1.note_type.beams() > 0 {
+                run.push(i);
+                continue;
+            }
+            if run.len() > 1 {
+                Self::beam_run(&run, notes, &mut beams);
+            }
+            run.clear();
+            if i < notes.len() && notes[i].1.note_type.beams() > 0 {
+                run.push(i);
+            }
+        }
+        beams
+    }
+
+    /// Set the beams of a run of notes which are beamed together. A secondary beam on a note without a neighbour
+    /// sharing it is drawn as a hook towards the previous note, or the next note if it is the first of the run.
+    fn beam_run(run: &[usize], notes: &[(Fraction, NoteLength)], beams: &mut [Vec<Beam>]) {
+        let counts: Vec<u8> = run.iter().map(|&i| notes[i].1.note_type.beams()).collect();
+        for level in 1..=counts.iter().cloned().max().unwrap_or(0) {
+            for (k, &i) in run.iter().enumerate() {
+                if counts[k] < level {
+                    continue;
+                }
+                let previous = k > 0 && counts[k - 1] >= level;
+                let next = k + 1 < run.len() && counts[k + 1] >= level;
+                beams[i].push(match (previous, next) {
+                    (false, true) => Beam::Begin,
+                    (true, true) => Beam::Continue,
+                    (true, false) => Beam::End,
+                    (false, false) if k == 0 => Beam::ForwardHook,
+                    (false, false) => Beam::BackwardHook,
+                });
+            }
+        }
+    }
+
+    /// Split a length into the largest note types first, ignoring the beats. Lengths whose denominator has an odd factor
+    /// are written as tuplets, and anything shorter than the shortest note type as a fraction of it, so that the lengths
+    /// always add up exactly.
+    fn split_greedy(length: Fraction) -> Vec<NoteLength> {
+        let denominator = length.denominator();
+        let actual = denominator >> denominator.trailing_zeros();
+        let normal = 1 << (31 - actual.leading_zeros());
+        let tuplet = if actual == 1 {
+            None
+        } else {
+            Some((actual as u32, normal as u32))
+        };
+        let (note_types, remainder) =
+            NoteType::from_fraction(length * Fraction::new(actual, normal));
+        let mut lengths: Vec<NoteLength> = note_types
+            .into_iter()
+            .map(|note_type| NoteLength {
+                tuplet,
+                ..NoteLength::from(note_type)
+            })
+            .collect();
+        if !remainder.is_zero() {
+            let ratio = remainder * Fraction::new(normal, actual) / NoteType::N1024th.get_value();
+            lengths.push(NoteLength {
+                tuplet: Some((ratio.denominator() as u32, ratio.numerator() as u32)),
+                ..NoteLength::from(NoteType::N1024th)
+            });
+        }
+        lengths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fraction::Fraction;
+    use crate::meter::Meter;
+    use crate::phrase_element::{Beam, NoteLength, NoteType};
+
+    fn split(time: (u8, u8), offset: (i32, i32), length: (i32, i32)) -> Vec<(NoteType, u8)> {
+        Meter::new(time)
+            .split(
+                Fraction::new(offset.0, offset.1),
+                Fraction::new(length.0, length.1),
+            )
+            .into_iter()
+            .map(
+                |NoteLength {
+                     note_type, dots, ..
+                 }| (note_type, dots),
+            )
+            .collect()
+    }
+
+    /// Beam a run of notes starting at the start of the bar.
+    fn beams(time: (u8, u8), notes: &[NoteType]) -> Vec<Vec<Beam>> {
+        let mut offset = Fraction::zero();
+        let notes: Vec<(Fraction, NoteLength)> = notes
+            .iter()
+            .map(|&note_type| {
+                let note = (offset, NoteLength::from(note_type));
+                offset += note_type.get_value();
+                note
+            })
+            .collect();
+        Meter::new(time).beams(&notes)
+    }
+
+    #[test]
+    fn simple_meter() {
+        // Dotted notes starting on a beat are kept whole.
+        assert_eq!(split((4, 4), (0, 1), (3, 2)), vec![(NoteType::Quarter, 1)]);
+        assert_eq!(split((4, 4), (0, 1), (3, 1)), vec![(NoteType::Half, 1)]);
+        assert_eq!(split((3, 4), (1, 1), (3, 2)), vec![(NoteType::Quarter, 1)]);
+        assert_eq!(split((3, 4), (1, 1), (2, 1)), vec![(NoteType::Half, 0)]);
+
+        // The middle of a 4/4 bar is shown.
+        assert_eq!(
+            split((4, 4), (1, 1), (2, 1)),
+            vec![(NoteType::Quarter, 0), (NoteType::Quarter, 0)]
+        );
+        assert_eq!(
+            split((4, 4), (0, 1), (5, 2)),
+            vec![(NoteType::Half, 0), (NoteType::Eighth, 0)]
+        );
+
+        // Syncopations are split at the beat.
+        assert_eq!(
+            split((2, 4), (1, 2), (1, 1)),
+            vec![(NoteType::Eighth, 0), (NoteType::Eighth, 0)]
+        );
+        assert_eq!(
+            split((3, 4), (1, 2), (5, 2)),
+            vec![(NoteType::Eighth, 0), (NoteType::Half, 0)]
+        );
+
+        // Syncopations within a beat are kept whole.
+        assert_eq!(split((2, 4), (1, 4), (1, 2)), vec![(NoteType::Eighth, 0)]);
+        assert_eq!(split((2, 4), (1, 4), (3, 4)), vec![(NoteType::Eighth, 1)]);
+    }
+
+    #[test]
+    fn compound_meter() {
+        assert_eq!(split((6, 8), (0, 1), (3, 1)), vec![(NoteType::Half, 1)]);
+        assert_eq!(split((6, 8), (0, 1), (3, 2)), vec![(NoteType::Quarter, 1)]);
+        assert_eq!(split((6, 8), (1, 2), (1, 1)), vec![(NoteType::Quarter, 0)]);
+
+        // A quarter across the middle of a 6/8 bar shows the second beat.
+        assert_eq!(
+            split((6, 8), (1, 1), (1, 1)),
+            vec![(NoteType::Eighth, 0), (NoteType::Eighth, 0)]
+        );
+        assert_eq!(
+            split((12, 8), (0, 1), (9, 2)),
+            vec![(NoteType::Half, 1), (NoteType::Quarter, 1)]
+        );
+    }
+
+    #[test]
+    fn irregular_meter() {
+        assert_eq!(
+            split((5, 4), (0, 1), (5, 1)),
+            vec![(NoteType::Half, 1), (NoteType::Half, 0)]
+        );
+        assert_eq!(split((7, 8), (0, 1), (7, 2)), vec![(NoteType::Half, 2)]);
+        assert_eq!(
+            split((7, 8), (1, 2), (1, 1)),
+            vec![(NoteType::Eighth, 0), (NoteType::Eighth, 0)]
+        );
+    }
+
+    #[test]
+    fn greedy_split() {
+        // A note running past the end of the bar is split greedily, as triplets if it's a number of triplets long.
+        let lengths = Meter::new((2, 4)).split(Fraction::zero(), Fraction::new(7, 3));
+        assert_eq!(
+            lengths
+                .iter()
+                .map(|length| (length.note_type, length.tuplet))
+                .collect::<Vec<_>>(),
+            vec![
+                (NoteType::Half, Some((3, 2))),
+                (NoteType::Quarter, Some((3, 2))),
+                (NoteType::Eighth, Some((3, 2)))
+            ]
+        );
+
+        // The lengths always add up exactly, even when they are shorter than the shortest note type.
+        for &(numerator, denominator) in &[(7, 3), (1, 768), (1025, 1024), (3, 7)] {
+            let length = Fraction::new(numerator, denominator);
+            let total = Meter::new((2, 4))
+                .split(Fraction::new(-1, 1), length)
+                .iter()
+                .fold(Fraction::zero(), |total, length| total + length.get_value());
+            assert_eq!(total, length);
+        }
+    }
+
+    #[test]
+    fn beaming() {
+        use Beam::*;
+        use NoteType::{Eighth, N16th, Quarter};
+
+        // Eighths are beamed by the beat in 2/4, and the quarter isn't beamed.
+        assert_eq!(
+            beams((2, 4), &[Eighth, Eighth, Eighth, Eighth]),
+            vec![vec![Begin], vec![End], vec![Begin], vec![End]]
+        );
+        assert_eq!(
+            beams((2, 4), &[Eighth, Eighth, Quarter]),
+            vec![vec![Begin], vec![End], vec![]]
+        );
+
+        // Eighths are beamed by the dotted quarter in 6/8, and across the bar in 3/8.
+        assert_eq!(
+            beams((6, 8), &[Eighth; 6]),
+            vec![
+                vec![Begin],
+                vec![Continue],
+                vec![End],
+                vec![Begin],
+                vec![Continue],
+                vec![End]
+            ]
+        );
+        assert_eq!(
+            beams((3, 8), &[Eighth; 3]),
+            vec![vec![Begin], vec![Continue], vec![End]]
+        );
+
+        // Sixteenths have a secondary beam, which is a hook on a sixteenth next to an eighth.
+        assert_eq!(
+            beams((2, 4), &[N16th, N16th, Eighth, Eighth, N16th]),
+            vec![
+                vec![Begin, Begin],
+                vec![Continue, End],
+                vec![End],
+                vec![Begin],
+                vec![End, BackwardHook]
+            ]
+        );
+    }
+}
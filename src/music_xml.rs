@@ -1,3 +1,4 @@
+use crate::fraction::Fraction;
 use crate::phrase_element::*;
 use quick_xml::events::attributes::Attribute;
 use quick_xml::{events::*, Writer};
@@ -49,6 +50,11 @@ impl MusicXML {
         }
     }
 
+    /// Set the number of the next bar to be written.
+    pub fn set_first_bar(&mut self, bar: u32) {
+        self.current_bar = bar;
+    }
+
     /// End a MusicXML document.
     pub fn end(&mut self) {
         self.xml
@@ -59,15 +65,16 @@ impl MusicXML {
             .unwrap();
     }
 
-    /// Start the bar and write the attributes.
+    /// Start the bar and write the attributes, including the number of bars in a multiple bar rest if one starts here.
     pub fn start_bar(
         &mut self,
-        divisions: NoteType,
+        divisions: u32,
         clefs: Vec<Option<Clef>>,
         key: Option<i8>,
         time: Option<(u8, u8)>,
+        multiple_rest: Option<usize>,
     ) {
-        self.current_divisions = divisions.to_divisions();
+        self.current_divisions = divisions;
         let mut measure = BytesStart::owned_name("measure");
         measure.push_attribute(("number", self.current_bar.to_string().as_str()));
         self.xml.write_event(Event::Start(measure)).unwrap();
@@ -171,6 +178,23 @@ impl MusicXML {
                     .unwrap();
             }
         }
+        if let Some(bars) = multiple_rest {
+            self.xml
+                .write_event(Event::Start(BytesStart::owned_name("measure-style")))
+                .unwrap();
+            self.xml
+                .write_event(Event::Start(BytesStart::owned_name("multiple-rest")))
+                .unwrap();
+            self.xml
+                .write_event(Event::Text(BytesText::from_plain_str(&bars.to_string())))
+                .unwrap();
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"multiple-rest")))
+                .unwrap();
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"measure-style")))
+                .unwrap();
+        }
         self.xml
             .write_event(Event::End(BytesEnd::borrowed(b"attributes")))
             .unwrap();
@@ -184,14 +208,29 @@ impl MusicXML {
         self.current_bar += 1;
     }
 
+    /// Get a length in the divisions of the current bar.
+    fn duration(&self, length: Fraction) -> u32 {
+        let duration = length * Fraction::new(self.current_divisions as i32, 1);
+        debug_assert_eq!(duration.denominator(), 1);
+        duration.to_whole() as u32
+    }
+
     /// Used to write elements common to notes and rests.
-    fn write_note_common(&mut self, length: NoteType, voice: u8, stave: u8, tie: Tie) {
+    /// The type is left out if there is no length, as for whole bar rests.
+    fn write_note_common(
+        &mut self,
+        duration: Fraction,
+        length: Option<NoteLength>,
+        voice: u8,
+        tie: Tie,
+        accidental: Option<Accidental>,
+    ) {
         self.xml
             .write_event(Event::Start(BytesStart::owned_name("duration")))
             .unwrap();
         self.xml
             .write_event(Event::Text(BytesText::from_plain_str(
-                &length.divisions(self.current_divisions).to_string(),
+                &self.duration(duration).to_string(),
             )))
             .unwrap();
         self.xml
@@ -231,17 +270,80 @@ impl MusicXML {
             .write_event(Event::End(BytesEnd::borrowed(b"voice")))
             .unwrap();
 
-        self.xml
-            .write_event(Event::Start(BytesStart::owned_name("type")))
-            .unwrap();
-        self.xml
-            .write_event(Event::Text(BytesText::from_plain_str(
-                &length.name().to_string(),
-            )))
-            .unwrap();
-        self.xml
-            .write_event(Event::End(BytesEnd::borrowed(b"type")))
-            .unwrap();
+        if let Some(length) = length {
+            self.xml
+                .write_event(Event::Start(BytesStart::owned_name("type")))
+                .unwrap();
+            self.xml
+                .write_event(Event::Text(BytesText::from_plain_str(
+                    &length.note_type.name().to_string(),
+                )))
+                .unwrap();
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"type")))
+                .unwrap();
+            for _ in 0..length.dots {
+                self.xml
+                    .write_event(Event::Empty(BytesStart::owned_name("dot")))
+                    .unwrap();
+            }
+            if let Some(accidental) = accidental {
+                let mut accidental_element = BytesStart::owned_name("accidental");
+                if accidental.cautionary {
+                    accidental_element.push_attribute(("cautionary", "yes"));
+                }
+                self.xml
+                    .write_event(Event::Start(accidental_element))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::Text(BytesText::from_plain_str(accidental.name())))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::End(BytesEnd::borrowed(b"accidental")))
+                    .unwrap();
+            }
+            if let Some((actual, normal)) = length.tuplet {
+                self.xml
+                    .write_event(Event::Start(BytesStart::owned_name("time-modification")))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::Start(BytesStart::owned_name("actual-notes")))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::Text(BytesText::from_plain_str(&actual.to_string())))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::End(BytesEnd::borrowed(b"actual-notes")))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::Start(BytesStart::owned_name("normal-notes")))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::Text(BytesText::from_plain_str(&normal.to_string())))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::End(BytesEnd::borrowed(b"normal-notes")))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::End(BytesEnd::borrowed(b"time-modification")))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Used to write the stem and stave of notes and rests.
+    fn write_staff(&mut self, stave: u8, stem: Option<Stem>) {
+        if let Some(stem) = stem {
+            self.xml
+                .write_event(Event::Start(BytesStart::owned_name("stem")))
+                .unwrap();
+            self.xml
+                .write_event(Event::Text(BytesText::from_plain_str(stem.name())))
+                .unwrap();
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"stem")))
+                .unwrap();
+        }
 
         self.xml
             .write_event(Event::Start(BytesStart::owned_name("staff")))
@@ -252,7 +354,10 @@ impl MusicXML {
         self.xml
             .write_event(Event::End(BytesEnd::borrowed(b"staff")))
             .unwrap();
+    }
 
+    /// Used to write the notations of notes and rests, which follow any beams.
+    fn write_notations(&mut self, tie: Tie, tuplet: Tie) {
         self.xml
             .write_event(Event::Start(BytesStart::owned_name("notations")))
             .unwrap();
@@ -278,90 +383,186 @@ impl MusicXML {
             }
             Tie::None => (),
         };
+        if tuplet.is_start() {
+            let mut tuplet = BytesStart::owned_name("tuplet");
+            tuplet.push_attribute(("type", "start"));
+            self.xml.write_event(Event::Empty(tuplet)).unwrap();
+        }
+        if tuplet.is_stop() {
+            let mut tuplet = BytesStart::owned_name("tuplet");
+            tuplet.push_attribute(("type", "stop"));
+            self.xml.write_event(Event::Empty(tuplet)).unwrap();
+        }
         self.xml
             .write_event(Event::End(BytesEnd::borrowed(b"notations")))
             .unwrap();
     }
 
-    /// Add a rest to a bar.
-    pub fn add_rest(&mut self, length: NoteType, voice: u8, stave: u8, bar_rest: bool) {
+    /// Add a rest to a bar, marking where a tuplet bracket starts or stops on it.
+    pub fn add_rest(&mut self, length: NoteLength, voice: u8, stave: u8, tuplet: Tie) {
         self.xml
             .write_event(Event::Start(BytesStart::owned_name("note")))
             .unwrap();
-        let mut rest = BytesStart::owned_name("rest");
-        if bar_rest {
-            rest.push_attribute(("measure", "yes"));
-        }
-        self.xml.write_event(Event::Empty(rest)).unwrap();
-        self.write_note_common(length, voice, stave, Tie::None);
+        self.xml
+            .write_event(Event::Empty(BytesStart::owned_name("rest")))
+            .unwrap();
+        self.write_note_common(length.get_value(), Some(length), voice, Tie::None, None);
+        self.write_staff(stave, None);
+        self.write_notations(Tie::None, tuplet);
         self.xml
             .write_event(Event::End(BytesEnd::borrowed(b"note")))
             .unwrap();
     }
 
-    /// Add a note to a bar.
-    pub fn add_note(&mut self, length: NoteType, note: Note, voice: u8, stave: u8, chord: bool) {
+    /// Add a rest filling a whole bar of the given length.
+    pub fn add_bar_rest(&mut self, bar_length: Fraction, voice: u8, stave: u8) {
         self.xml
             .write_event(Event::Start(BytesStart::owned_name("note")))
             .unwrap();
+        let mut rest = BytesStart::owned_name("rest");
+        rest.push_attribute(("measure", "yes"));
+        self.xml.write_event(Event::Empty(rest)).unwrap();
+        self.write_note_common(bar_length, None, voice, Tie::None, None);
+        self.write_staff(stave, None);
+        self.write_notations(Tie::None, Tie::None);
+        self.xml
+            .write_event(Event::End(BytesEnd::borrowed(b"note")))
+            .unwrap();
+    }
+
+    /// Add a note or chord to a bar, giving the stave each note is displayed on, optionally its colour and the
+    /// accidental written before it. The beams are numbered from the primary beam, and are written on the first note of
+    /// a chord along with where a tuplet bracket starts or stops.
+    pub fn add_chord(
+        &mut self,
+        length: NoteLength,
+        notes: &[(Note, u8, Option<&str>, Option<Accidental>)],
+        voice: u8,
+        stem: Option<Stem>,
+        beams: &[Beam],
+        tuplet: Tie,
+    ) {
+        for (i, &(note, stave, colour, accidental)) in notes.iter().enumerate() {
+            let mut note_element = BytesStart::owned_name("note");
+            if let Some(colour) = colour {
+                note_element.push_attribute(("color", colour));
+            }
+            self.xml.write_event(Event::Start(note_element)).unwrap();
+
+            if i > 0 {
+                self.xml
+                    .write_event(Event::Empty(BytesStart::owned_name("chord")))
+                    .unwrap();
+            }
 
-        if chord {
             self.xml
-                .write_event(Event::Empty(BytesStart::owned_name("chord")))
+                .write_event(Event::Start(BytesStart::owned_name("pitch")))
                 .unwrap();
-        }
 
-        self.xml
-            .write_event(Event::Start(BytesStart::owned_name("pitch")))
-            .unwrap();
+            self.xml
+                .write_event(Event::Start(BytesStart::owned_name("step")))
+                .unwrap();
+            self.xml
+                .write_event(Event::Text(BytesText::from_plain_str(note.step.name())))
+                .unwrap();
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"step")))
+                .unwrap();
+            if note.alter != 0 {
+                self.xml
+                    .write_event(Event::Start(BytesStart::owned_name("alter")))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::Text(BytesText::from_plain_str(
+                        &note.alter.to_string(),
+                    )))
+                    .unwrap();
+                self.xml
+                    .write_event(Event::End(BytesEnd::borrowed(b"alter")))
+                    .unwrap();
+            }
 
-        self.xml
-            .write_event(Event::Start(BytesStart::owned_name("step")))
-            .unwrap();
-        self.xml
-            .write_event(Event::Text(BytesText::from_plain_str(note.step.name())))
-            .unwrap();
-        self.xml
-            .write_event(Event::End(BytesEnd::borrowed(b"step")))
-            .unwrap();
-        if note.alter != 0 {
             self.xml
-                .write_event(Event::Start(BytesStart::owned_name("alter")))
+                .write_event(Event::Start(BytesStart::owned_name("octave")))
                 .unwrap();
             self.xml
                 .write_event(Event::Text(BytesText::from_plain_str(
-                    &note.alter.to_string(),
+                    &note.octave.to_string(),
                 )))
                 .unwrap();
             self.xml
-                .write_event(Event::End(BytesEnd::borrowed(b"alter")))
+                .write_event(Event::End(BytesEnd::borrowed(b"octave")))
+                .unwrap();
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"pitch")))
+                .unwrap();
+
+            self.write_note_common(
+                length.get_value(),
+                Some(length),
+                voice,
+                note.tie,
+                accidental,
+            );
+            self.write_staff(stave, stem);
+            if i == 0 {
+                for (number, beam) in beams.iter().enumerate() {
+                    let mut beam_element = BytesStart::owned_name("beam");
+                    beam_element.push_attribute(("number", (number + 1).to_string().as_str()));
+                    self.xml.write_event(Event::Start(beam_element)).unwrap();
+                    self.xml
+                        .write_event(Event::Text(BytesText::from_plain_str(beam.name())))
+                        .unwrap();
+                    self.xml
+                        .write_event(Event::End(BytesEnd::borrowed(b"beam")))
+                        .unwrap();
+                }
+            }
+            self.write_notations(note.tie, if i == 0 { tuplet } else { Tie::None });
+
+            self.xml
+                .write_event(Event::End(BytesEnd::borrowed(b"note")))
                 .unwrap();
         }
+    }
 
+    /// Add a text direction above or below a stave.
+    pub fn add_words(&mut self, words: &str, stave: u8, above: bool) {
+        let mut direction = BytesStart::owned_name("direction");
+        direction.push_attribute(("placement", if above { "above" } else { "below" }));
+        self.xml.write_event(Event::Start(direction)).unwrap();
         self.xml
-            .write_event(Event::Start(BytesStart::owned_name("octave")))
+            .write_event(Event::Start(BytesStart::owned_name("direction-type")))
             .unwrap();
         self.xml
-            .write_event(Event::Text(BytesText::from_plain_str(
-                &note.octave.to_string(),
-            )))
+            .write_event(Event::Start(BytesStart::owned_name("words")))
             .unwrap();
         self.xml
-            .write_event(Event::End(BytesEnd::borrowed(b"octave")))
+            .write_event(Event::Text(BytesText::from_plain_str(words)))
             .unwrap();
         self.xml
-            .write_event(Event::End(BytesEnd::borrowed(b"pitch")))
+            .write_event(Event::End(BytesEnd::borrowed(b"words")))
+            .unwrap();
+        self.xml
+            .write_event(Event::End(BytesEnd::borrowed(b"direction-type")))
             .unwrap();
-
-        self.write_note_common(length, voice, stave, note.tie);
 
         self.xml
-            .write_event(Event::End(BytesEnd::borrowed(b"note")))
+            .write_event(Event::Start(BytesStart::owned_name("staff")))
+            .unwrap();
+        self.xml
+            .write_event(Event::Text(BytesText::from_plain_str(&stave.to_string())))
+            .unwrap();
+        self.xml
+            .write_event(Event::End(BytesEnd::borrowed(b"staff")))
+            .unwrap();
+        self.xml
+            .write_event(Event::End(BytesEnd::borrowed(b"direction")))
             .unwrap();
     }
 
     /// Add a backup element to a bar.
-    pub fn backup(&mut self, time: NoteType) {
+    pub fn backup(&mut self, time: Fraction) {
         self.xml
             .write_event(Event::Start(BytesStart::owned_name("backup")))
             .unwrap();
@@ -370,7 +571,7 @@ impl MusicXML {
             .unwrap();
         self.xml
             .write_event(Event::Text(BytesText::from_plain_str(
-                &time.divisions(self.current_divisions).to_string(),
+                &self.duration(time).to_string(),
             )))
             .unwrap();
         self.xml
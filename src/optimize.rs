@@ -0,0 +1,237 @@
+use crate::hand_model::HandModel;
+use crate::reduction::{Reduction, Strategy};
+use crate::report::Report;
+use crate::score_representation::{ScoreParser, StaveList};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// The numbers of staves tried when searching for the best reduction.
+const STAVES: [u8; 2] = [2, 3];
+/// The handspans tried, in semitones.
+const HANDSPANS: [u32; 3] = [12, 14, 16];
+/// The maximum phrase lengths tried, in bars.
+const PHRASE_LENGTHS: [u32; 4] = [0, 1, 2, 4];
+/// The strategies tried for allocating phrases to staves.
+const STRATEGIES: [Strategy; 3] = [
+    Strategy::DistributeStaves,
+    Strategy::MergeByAverage,
+    Strategy::GroupFamilies,
+];
+
+/// A combination of options tried while searching for the best reduction, along with the report on its result.
+struct Candidate {
+    phrase_length: u32,
+    reduction: Reduction,
+    report: Report,
+}
+
+/// Run the reduction over every combination of staves, handspan, phrase length and strategy in parallel, keeping the
+/// other options of the base reduction. Returns the options and result of the reduction with the best score, and a
+/// table of every combination tried with the best first.
+pub fn optimize(parser: &mut ScoreParser, base: &Reduction) -> (Reduction, StaveList, String) {
+    let mut grid = Vec::new();
+    for &phrase_length in &PHRASE_LENGTHS {
+        for &staves in &STAVES {
+            for &handspan in &HANDSPANS {
+                for &strategy in &STRATEGIES {
+                    let reduction = Reduction {
+                        staves,
+                        hand: HandModel::new(handspan, base.hand.fingers),
+                        strategy,
+                        part_mapping: None,
+                        ..base.clone()
+                    };
+                    grid.push((phrase_length, reduction));
+                }
+            }
+        }
+    }
+    search(parser, grid)
+}
+
+/// Run the reduction with each phrase length and set of options in a grid in parallel, returning the options and
+/// result of the reduction with the best score, and a table of every combination tried with the best first. On a tie
+/// the earliest in the grid is best.
+fn search(parser: &mut ScoreParser, grid: Vec<(u32, Reduction)>) -> (Reduction, StaveList, String) {
+    let mut lengths: Vec<u32> = grid.iter().map(|(length, _)| *length).collect();
+    lengths.sort_unstable();
+    lengths.dedup();
+    let phrase_lists: Vec<_> = lengths
+        .iter()
+        .map(|&length| parser.parse_score(length))
+        .collect();
+    let jobs: Vec<_> = grid
+        .into_iter()
+        .map(|(phrase_length, reduction)| {
+            let length_index = lengths.binary_search(&phrase_length).unwrap();
+            (length_index, phrase_length, reduction)
+        })
+        .collect();
+
+    let next_job = AtomicUsize::new(0);
+    let candidates = Mutex::new(Vec::new());
+    let best: Mutex<Option<(f64, usize, StaveList)>> = Mutex::new(None);
+    let num_threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(jobs.len());
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index = next_job.fetch_add(1, Ordering::SeqCst);
+                let (length_index, phrase_length, reduction) = match jobs.get(index) {
+                    Some(job) => job,
+                    None => break,
+                };
+                let phrase_list = &phrase_lists[*length_index];
+                let stave_list = reduction.reduce(phrase_list.clone());
+                let report = Report::new(phrase_list.phrases(), &stave_list, &reduction.hand);
+                let score = report.score();
+
+                // Keep the earliest job on a tie so the result doesn't depend on the order the threads finish in.
+                let mut best = best.lock().unwrap();
+                let is_best = match &*best {
+                    Some((best_score, best_index, _)) => {
+                        score > *best_score || (score == *best_score && index < *best_index)
+                    }
+                    None => true,
+                };
+                if is_best {
+                    *best = Some((score, index, stave_list));
+                }
+                drop(best);
+
+                candidates.lock().unwrap().push((
+                    index,
+                    Candidate {
+                        phrase_length: *phrase_length,
+                        reduction: reduction.clone(),
+                        report,
+                    },
+                ));
+            });
+        }
+    });
+
+    let mut candidates: Vec<(usize, Candidate)> = candidates.into_inner().unwrap();
+    let (_, best_index, stave_list) = best.into_inner().unwrap().unwrap();
+    candidates.sort_by(|(a_index, a), (b_index, b)| {
+        b.report
+            .score()
+            .partial_cmp(&a.report.score())
+            .unwrap()
+            .then(a_index.cmp(b_index))
+    });
+    let table = table(candidates.iter().map(|(_, c)| c));
+    let (_, best) = candidates
+        .into_iter()
+        .find(|(index, _)| *index == best_index)
+        .unwrap();
+    (best.reduction, stave_list, table)
+}
+
+/// Write a table of candidates.
+fn table<'a>(candidates: impl Iterator<Item = &'a Candidate>) -> String {
+    let mut table = format!(
+        "{:>6}  {:>8}  {:>13}  {:10}  {:>7}  {:>6}  {:>10}  {:>7}  {:>5}\n",
+        "Staves",
+        "Handspan",
+        "Phrase length",
+        "Strategy",
+        "Harmony",
+        "Onsets",
+        "Violations",
+        "Dropped",
+        "Score"
+    );
+    for candidate in candidates {
+        let report = &candidate.report;
+        table.push_str(&format!(
+            "{:>6}  {:>8}  {:>13}  {:10}  {:>7.3}  {:>5.1}%  {:>10}  {:>7}  {:>5.3}\n",
+            candidate.reduction.staves,
+            candidate.reduction.hand.span,
+            candidate.phrase_length,
+            candidate.reduction.strategy.name(),
+            report.harmony.overall,
+            report.onsets_kept_ratio() * 100.0,
+            report.violating_bars(),
+            report.dropped,
+            report.score()
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hand_model::HandModel;
+    use crate::optimize;
+    use crate::reduction::{Reduction, Strategy};
+    use crate::score_representation::ScoreParser;
+
+    /// A bar with a whole note C6 in one part against a C3 in another.
+    const SCORE: &str = r#"<score-partwise>
+  <part-list>
+    <score-part id="P1"><part-name>Flute</part-name></score-part>
+    <score-part id="P2"><part-name>Cello</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>1</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>C</step><octave>6</octave></pitch><duration>4</duration><voice>1</voice></note>
+    </measure>
+  </part>
+  <part id="P2">
+    <measure number="1">
+      <attributes><divisions>1</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>C</step><octave>3</octave></pitch><duration>4</duration><voice>1</voice></note>
+    </measure>
+  </part>
+</score-partwise>"#;
+
+    fn reduction(staves: u8, handspan: u32) -> Reduction {
+        Reduction {
+            staves,
+            hand: HandModel::new(handspan, 5),
+            strategy: Strategy::DistributeStaves,
+            part_mapping: None,
+            remove_doublings: false,
+            keep_octaves: false,
+            adjust_octaves: false,
+            merge: true,
+            merge_voices: false,
+            repair_chords: false,
+        }
+    }
+
+    #[test]
+    fn search() {
+        let doc = roxmltree::Document::parse(SCORE).unwrap();
+        let mut parser = ScoreParser::new(doc);
+        let grid = vec![
+            (0, reduction(1, 12)),
+            (0, reduction(2, 12)),
+            (1, reduction(2, 14)),
+        ];
+        let (best, stave_list, table) = optimize::search(&mut parser, grid);
+
+        // Three octaves can't be stretched on one stave, so the first two stave reduction in the grid is best.
+        assert_eq!((best.staves, best.hand.span), (2, 12));
+        assert_eq!(stave_list.staves.len(), 2);
+
+        // The table has a heading and a row for each candidate, with the best first.
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("Staves  Handspan"));
+        let columns = |row: &str| {
+            row.split_whitespace()
+                .map(|column| column.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(&columns(rows[1])[..4], ["2", "12", "0", "distribute"]);
+        assert_eq!(&columns(rows[2])[..4], ["2", "14", "1", "distribute"]);
+        assert_eq!(&columns(rows[3])[..4], ["1", "12", "0", "distribute"]);
+        assert_eq!(columns(rows[3])[6], "1");
+    }
+}
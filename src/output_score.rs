@@ -1,23 +1,125 @@
+use crate::accidentals::AccidentalState;
+use crate::colour::ColourPalette;
 use crate::fraction::Fraction;
+use crate::meter::Meter;
 use crate::music_xml::MusicXML;
 use crate::phrase::Phrase;
 use crate::phrase_element::*;
 use crate::score_representation::*;
-use std::collections::BTreeMap;
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Options controlling how a StaveList is written out.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Colour each note by the part it came from.
+    pub colours: Option<ColourPalette>,
+    /// Label entries on each stave with the abbreviated names of the parts they came from.
+    pub cue_labels: bool,
+    /// The number of the first bar, if it isn't 1.
+    pub first_bar: Option<u32>,
+    /// The most voices written on a stave, from 2 to 4.
+    pub max_voices: usize,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            colours: None,
+            cue_labels: false,
+            first_bar: None,
+            max_voices: 4,
+        }
+    }
+}
+
+/// Something written in a bar, collected before the bar is started so that its divisions can fit all of them.
+enum BarEvent {
+    Rest {
+        length: NoteLength,
+        voice: u8,
+        stave: u8,
+        /// Where a tuplet bracket starts or stops, marked as for a tie.
+        tuplet: Tie,
+    },
+    BarRest {
+        length: Fraction,
+        voice: u8,
+        stave: u8,
+    },
+    Backup(Fraction),
+    Words {
+        words: String,
+        stave: u8,
+        above: bool,
+    },
+    Note {
+        length: NoteLength,
+        element: PhraseElement,
+        start: Fraction,
+        voice: u8,
+        stave: u8,
+        stem: Option<Stem>,
+        beams: Vec<Beam>,
+        /// The accidental written before each note of the element.
+        accidentals: Vec<Option<Accidental>>,
+        /// Where a tuplet bracket starts or stops, marked as for a tie.
+        tuplet: Tie,
+    },
+}
 
 pub struct OutputScore {
     xml: MusicXML,
 }
 
+/// A note written in the voice of one stave but displayed on another.
+struct CrossStaffNote {
+    stave: u8,
+    start: Fraction,
+    end: Fraction,
+    value: u8,
+    display: u8,
+}
+
 impl OutputScore {
     /// Convert a StaveList to a MusicXML document.
-    pub fn new(stave_list: StaveList) -> Self {
+    pub fn new(stave_list: StaveList, options: &OutputOptions) -> Self {
         let num_staves = stave_list.staves.len();
+        let mut staves = stave_list.staves;
+        let cross_staff = Self::place_cross_staff_notes(&mut staves);
+        let staves: Vec<Vec<Phrase>> = staves
+            .into_iter()
+            .map(|stave| Self::pack_voices(stave, options.max_voices))
+            .collect();
+        let display_stave = |note: &Note, start: Fraction, stave: u8| {
+            cross_staff
+                .iter()
+                .find(|c| {
+                    c.stave == stave && c.value == note.value() && c.start <= start && start < c.end
+                })
+                .map(|c| c.display)
+                .unwrap_or(stave)
+        };
+        let parts = &stave_list.parts;
+        let colour = |note: &Note| match (&options.colours, note.source) {
+            (Some(colours), Some(source)) => Some(colours.colour(source.part, &parts[source.part])),
+            _ => None,
+        };
+        let mut cues: Vec<BTreeMap<Fraction, String>> = if options.cue_labels {
+            staves
+                .iter()
+                .map(|stave| Self::cue_labels(stave, parts))
+                .collect()
+        } else {
+            vec![BTreeMap::new(); num_staves]
+        };
         let mut xml = MusicXML::new();
+        if let Some(first_bar) = options.first_bar {
+            xml.set_first_bar(first_bar);
+        }
         let bar_numbers = BarNumbers::new(&stave_list.times);
         let mut phrase_bars: Vec<Vec<(Phrase, u8)>> = Vec::new();
-        let mut divisions: Vec<Fraction> = Vec::new();
-        for (stave, phrases) in stave_list.staves.into_iter().enumerate() {
+        for (stave, phrases) in staves.into_iter().enumerate() {
             for phrase in phrases {
                 let mut current_phrase = phrase;
                 while current_phrase.num_elements() > 0 {
@@ -25,28 +127,15 @@ impl OutputScore {
                     let bar_num = bar_numbers.get_bar_number(start);
                     if bar_num + 1 > phrase_bars.len() {
                         phrase_bars.resize_with(bar_num + 1, Default::default);
-                        divisions.resize_with(bar_num + 1, Default::default);
                     }
 
                     if let Some(split_point) =
                         bar_numbers.crosses_bar(start, current_phrase.length())
                     {
                         let (phrase1, phrase2) = current_phrase.split(split_point);
-                        if divisions[bar_num] == Fraction::zero() {
-                            divisions[bar_num] = phrase1.min_duration();
-                        } else {
-                            divisions[bar_num] = divisions[bar_num].min(phrase1.min_duration());
-                        }
-
                         phrase_bars[bar_num].push((phrase1, (stave + 1) as u8));
                         current_phrase = phrase2;
                     } else {
-                        if divisions[bar_num] == Fraction::zero() {
-                            divisions[bar_num] = current_phrase.min_duration();
-                        } else {
-                            divisions[bar_num] =
-                                divisions[bar_num].min(current_phrase.min_duration());
-                        }
                         phrase_bars[bar_num].push((current_phrase, (stave + 1) as u8));
                         current_phrase = Phrase::default();
                     }
@@ -54,35 +143,57 @@ impl OutputScore {
             }
         }
 
+        // Runs of bars with nothing on any stave are shown as multiple bar rests, broken at key and time changes.
+        let empty: Vec<bool> = phrase_bars.iter().map(|bar| bar.is_empty()).collect();
+        let mut changes = vec![false; phrase_bars.len()];
+        for position in stave_list.keys.keys().chain(stave_list.times.keys()) {
+            if let Some(change) = changes.get_mut(bar_numbers.get_bar_number(*position)) {
+                *change = true;
+            }
+        }
+        let multiple_rests = Self::multiple_rests(&empty, &changes);
+
+        let stave_clefs = if num_staves == 1 {
+            vec![Clef::Treble]
+        } else {
+            let mut clefs = vec![Clef::Treble; num_staves - 1];
+            clefs.push(Clef::Bass);
+            clefs
+        };
+        let mut accidentals = vec![AccidentalState::default(); num_staves];
         let mut current_pos = Fraction::zero();
         let mut current_time = *stave_list.times.get(&Fraction::zero()).unwrap();
         for (bar_num, mut bar) in phrase_bars.into_iter().enumerate() {
-            let smallest = NoteType::from_fraction(divisions[bar_num]);
             let key = stave_list.keys.get(&current_pos).cloned();
             let time = stave_list.times.get(&current_pos).cloned();
             let clefs = if bar_num == 0 {
-                if num_staves == 1 {
-                    vec![Some(Clef::Treble)]
-                } else {
-                    let mut clefs = vec![Some(Clef::Treble); num_staves - 1];
-                    clefs.push(Some(Clef::Bass));
-                    clefs
-                }
+                stave_clefs.iter().cloned().map(Some).collect()
             } else {
                 Vec::new()
             };
             if let Some(time) = time {
                 current_time = time;
             }
+            let meter = Meter::new(current_time);
+            let bar_start = current_pos;
             let bar_end =
                 Fraction::new(4 * current_time.0 as i32, current_time.1 as i32) + current_pos;
-            xml.start_bar(
-                smallest.into_iter().min().unwrap_or(NoteType::Quarter),
-                clefs,
-                key,
-                time,
-            );
-            bar.sort_unstable_by_key(|(_, a)| *a);
+
+            // Work out everything written in the bar first, so that the divisions can fit the shortest of them.
+            let mut events = Vec::new();
+            for stave in 1..=num_staves as u8 {
+                if !bar.iter().any(|(_, s)| *s == stave) {
+                    bar.push((Phrase::default(), stave));
+                }
+            }
+            bar.sort_by_key(|(_, a)| *a);
+            // Where a stave has more than one voice in the bar, the odd voices have stems up and the even ones down.
+            let mut stave_voices = vec![0; num_staves + 1];
+            for (phrase, stave) in &bar {
+                if phrase.num_elements() > 0 {
+                    stave_voices[*stave as usize] += 1;
+                }
+            }
             let mut voice = 1;
             let mut last_stave = 0;
             for (phrase, stave) in bar {
@@ -91,26 +202,52 @@ impl OutputScore {
                 } else {
                     voice += 1;
                 }
+                let stem = match (stave_voices[stave as usize], (voice - 1) % 4 % 2) {
+                    (0..=1, _) => None,
+                    (_, 0) => Some(Stem::Up),
+                    _ => Some(Stem::Down),
+                };
 
+                // Every voice is written from the start of the bar.
+                if current_pos > bar_start {
+                    events.push(BarEvent::Backup(current_pos - bar_start));
+                    current_pos = bar_start;
+                }
                 if phrase.num_elements() == 0 {
-                    xml.add_rest(NoteType::Whole, voice, stave, true);
+                    events.push(BarEvent::BarRest {
+                        length: bar_end - bar_start,
+                        voice,
+                        stave,
+                    });
+                    current_pos = bar_end;
                 } else {
                     for (start, (element, length)) in phrase.elements() {
                         if start > current_pos {
-                            let rests = NoteType::from_fraction(start - current_pos);
+                            let rests = meter.split(current_pos - bar_start, start - current_pos);
                             for rest in rests {
-                                xml.add_rest(rest, voice, stave, false);
+                                events.push(BarEvent::Rest {
+                                    length: rest,
+                                    voice,
+                                    stave,
+                                    tuplet: Tie::None,
+                                });
                                 current_pos += rest.get_value();
                             }
                         } else if start < current_pos {
-                            let backups = NoteType::from_fraction(current_pos - start);
-                            for backup in backups {
-                                xml.backup(backup);
-                                current_pos -= backup.get_value();
-                            }
+                            events.push(BarEvent::Backup(current_pos - start));
+                            current_pos = start;
                         }
-                        let lengths = NoteType::from_fraction(length);
+                        if let Some(words) = cues[(stave - 1) as usize].remove(&start) {
+                            let above = num_staves == 1 || (stave as usize) < num_staves;
+                            events.push(BarEvent::Words {
+                                words,
+                                stave,
+                                above,
+                            });
+                        }
+                        let lengths = meter.split(start - bar_start, length);
                         let num_notes = lengths.len();
+                        let mut note_start = start;
                         for (i, length) in lengths.into_iter().enumerate() {
                             let mut element = element.clone();
                             if num_notes > 1 {
@@ -123,35 +260,105 @@ impl OutputScore {
                                     element.start_tie();
                                 }
                             }
-                            match element {
-                                PhraseElement::Note(note) => {
-                                    xml.add_note(length, note, voice, stave, false)
-                                }
-                                PhraseElement::Chord(ref chord) => match chord.as_slice() {
-                                    [] => (),
-                                    [x, xs @ ..] => {
-                                        xml.add_note(length, *x, voice, stave, false);
-                                        for note in xs {
-                                            xml.add_note(length, *note, voice, stave, true);
-                                        }
-                                    }
-                                },
-                            }
+                            let accidentals = vec![None; element.notes().len()];
+                            events.push(BarEvent::Note {
+                                length,
+                                element,
+                                start: note_start,
+                                voice,
+                                stave,
+                                stem,
+                                beams: Vec::new(),
+                                accidentals,
+                                tuplet: Tie::None,
+                            });
+                            note_start += length.get_value();
                         }
                         current_pos += length;
                     }
                 }
 
                 if current_pos < bar_end {
-                    let rests = NoteType::from_fraction(bar_end - current_pos);
+                    let rests = meter.split(current_pos - bar_start, bar_end - current_pos);
                     for rest in rests {
-                        xml.add_rest(rest, voice, stave, false);
+                        events.push(BarEvent::Rest {
+                            length: rest,
+                            voice,
+                            stave,
+                            tuplet: Tie::None,
+                        });
                         current_pos += rest.get_value();
                     }
                 }
 
                 last_stave = stave;
             }
+
+            Self::beam_notes(&mut events, &meter, bar_start);
+            Self::bracket_tuplets(&mut events);
+            Self::set_stems(&mut events, &stave_clefs);
+            for state in &mut accidentals {
+                state.start_bar(key);
+            }
+            Self::set_accidentals(&mut events, &mut accidentals, display_stave);
+
+            // Every length in the bar is a whole number of divisions, as is every position since they are sums of lengths.
+            let divisions = events
+                .iter()
+                .filter_map(|event| match event {
+                    BarEvent::Rest { length, .. } | BarEvent::Note { length, .. } => {
+                        Some(length.get_value())
+                    }
+                    BarEvent::BarRest { length, .. } | BarEvent::Backup(length) => Some(*length),
+                    BarEvent::Words { .. } => None,
+                })
+                .fold(1, |divisions, length| {
+                    Fraction::lcm(divisions, length.denominator())
+                });
+            xml.start_bar(divisions as u32, clefs, key, time, multiple_rests[bar_num]);
+            for event in events {
+                match event {
+                    BarEvent::Rest {
+                        length,
+                        voice,
+                        stave,
+                        tuplet,
+                    } => xml.add_rest(length, voice, stave, tuplet),
+                    BarEvent::BarRest {
+                        length,
+                        voice,
+                        stave,
+                    } => xml.add_bar_rest(length, voice, stave),
+                    BarEvent::Backup(length) => xml.backup(length),
+                    BarEvent::Words {
+                        words,
+                        stave,
+                        above,
+                    } => xml.add_words(&words, stave, above),
+                    BarEvent::Note {
+                        length,
+                        element,
+                        start,
+                        voice,
+                        stave,
+                        stem,
+                        beams,
+                        accidentals,
+                        tuplet,
+                    } => {
+                        let notes: Vec<(Note, u8, Option<&str>, Option<Accidental>)> = element
+                            .notes()
+                            .iter()
+                            .zip(accidentals)
+                            .map(|(note, accidental)| {
+                                let display = display_stave(note, start, stave);
+                                (*note, display, colour(note), accidental)
+                            })
+                            .collect();
+                        xml.add_chord(length, &notes, voice, stem, &beams, tuplet);
+                    }
+                }
+            }
             xml.end_bar()
         }
 
@@ -160,6 +367,294 @@ impl OutputScore {
         OutputScore { xml }
     }
 
+    /// Beam together the notes of each voice in a bar, breaking the beams at rests.
+    fn beam_notes(events: &mut [BarEvent], meter: &Meter, bar_start: Fraction) {
+        let mut runs = vec![Vec::new()];
+        for (i, event) in events.iter().enumerate() {
+            match event {
+                BarEvent::Note { start, length, .. } => runs
+                    .last_mut()
+                    .unwrap()
+                    .push((i, (*start - bar_start, *length))),
+                BarEvent::Words { .. } => (),
+                _ => runs.push(Vec::new()),
+            }
+        }
+        for run in runs {
+            let notes: Vec<(Fraction, NoteLength)> = run.iter().map(|(_, note)| *note).collect();
+            for ((i, _), note_beams) in run.into_iter().zip(meter.beams(&notes)) {
+                if let BarEvent::Note { beams, .. } = &mut events[i] {
+                    *beams = note_beams;
+                }
+            }
+        }
+    }
+
+    /// Bracket together the notes and rests of each voice in a bar which are played as the same tuplet. A bracket is
+    /// closed once its notes fill a whole number of the notes they are played in the time of, or when the tuplet ends.
+    fn bracket_tuplets(events: &mut [BarEvent]) {
+        // The ratio of the open bracket, the length of the notes in it so far, and its last note.
+        let mut open: Option<((u32, u32), Fraction, usize)> = None;
+        for i in 0..events.len() {
+            let tuplet = match &events[i] {
+                BarEvent::Rest { length, .. } | BarEvent::Note { length, .. } => {
+                    length.tuplet.map(|ratio| (ratio, length.get_value()))
+                }
+                BarEvent::Words { .. } => continue,
+                _ => None,
+            };
+            if let Some((ratio, _, last)) = open {
+                if tuplet.map(|(r, _)| r) != Some(ratio) {
+                    Self::tuplet_marker(&mut events[last]).stop();
+                    open = None;
+                }
+            }
+            if let Some((ratio, value)) = tuplet {
+                let total = match open {
+                    Some((_, total, _)) => total + value,
+                    None => {
+                        Self::tuplet_marker(&mut events[i]).start();
+                        value
+                    }
+                };
+                let denominator = total.denominator();
+                if denominator & (denominator - 1) == 0 {
+                    Self::tuplet_marker(&mut events[i]).stop();
+                    open = None;
+                } else {
+                    open = Some((ratio, total, i));
+                }
+            }
+        }
+        if let Some((_, _, last)) = open {
+            Self::tuplet_marker(&mut events[last]).stop();
+        }
+    }
+
+    /// Get the tuplet bracket marker of a note or rest.
+    fn tuplet_marker(event: &mut BarEvent) -> &mut Tie {
+        match event {
+            BarEvent::Rest { tuplet, .. } | BarEvent::Note { tuplet, .. } => tuplet,
+            _ => panic!("Only notes and rests can be part of a tuplet"),
+        }
+    }
+
+    /// Point the stems of notes in voices written alone on their stave away from the middle line. A chord or a group of
+    /// beamed notes follows whichever of its notes is furthest from the middle line, with the stems pointing down if
+    /// they are as far above it as below. Notes of a whole or longer have no stem.
+    fn set_stems(events: &mut [BarEvent], clefs: &[Clef]) {
+        let mut group = Vec::new();
+        for i in 0..events.len() {
+            match &events[i] {
+                BarEvent::Note {
+                    stem: None, beams, ..
+                } => {
+                    group.push(i);
+                    if let Some(Beam::Begin) | Some(Beam::Continue) = beams.first() {
+                        continue;
+                    }
+                }
+                _ => continue,
+            }
+
+            let positions: Vec<i32> = group
+                .iter()
+                .flat_map(|&j| match &events[j] {
+                    BarEvent::Note { element, stave, .. } => {
+                        let middle = clefs[(stave - 1) as usize].middle_line();
+                        element
+                            .notes()
+                            .iter()
+                            .map(|note| note.staff_position() - middle)
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                })
+                .collect();
+            if let (Some(highest), Some(lowest)) = (positions.iter().max(), positions.iter().min())
+            {
+                let direction = if highest + lowest >= 0 {
+                    Stem::Down
+                } else {
+                    Stem::Up
+                };
+                for &j in &group {
+                    if let BarEvent::Note { length, stem, .. } = &mut events[j] {
+                        if length.note_type < NoteType::Whole {
+                            *stem = Some(direction);
+                        }
+                    }
+                }
+            }
+            group.clear();
+        }
+    }
+
+    /// Work out the accidentals of the notes in a bar, going through the notes displayed on each stave in the order they
+    /// are played.
+    fn set_accidentals(
+        events: &mut [BarEvent],
+        staves: &mut [AccidentalState],
+        display_stave: impl Fn(&Note, Fraction, u8) -> u8,
+    ) {
+        let mut notes = Vec::new();
+        for (i, event) in events.iter().enumerate() {
+            if let BarEvent::Note {
+                element,
+                start,
+                stave,
+                ..
+            } = event
+            {
+                for (n, note) in element.notes().iter().enumerate() {
+                    notes.push((display_stave(note, *start, *stave), *start, i, n, *note));
+                }
+            }
+        }
+        notes.sort_by_key(|&(stave, start, ..)| (stave, start));
+        for (stave, _, i, n, note) in notes {
+            let accidental = staves[(stave - 1) as usize].accidental(&note);
+            if let BarEvent::Note { accidentals, .. } = &mut events[i] {
+                accidentals[n] = accidental;
+            }
+        }
+    }
+
+    /// Move notes played by the hand of another stave into the voice of the stave they belong to, and return where each
+    /// of them should be displayed.
+    fn place_cross_staff_notes(staves: &mut [Vec<Phrase>]) -> Vec<CrossStaffNote> {
+        let num_staves = staves.len();
+        let mut cross_staff = Vec::new();
+        for stave in 0..num_staves {
+            let mut moved = Vec::new();
+            for phrase in &mut staves[stave] {
+                let notes: Vec<(Fraction, Fraction, Note)> = phrase
+                    .elements_ref()
+                    .iter()
+                    .flat_map(|(position, (element, length))| {
+                        element
+                            .notes()
+                            .iter()
+                            .map(move |n| (*position, *length, *n))
+                    })
+                    .filter(|(_, _, n)| match n.cross_staff {
+                        Some(home) => home as usize != stave + 1 && home as usize <= num_staves,
+                        None => false,
+                    })
+                    .collect();
+                for (position, length, note) in notes {
+                    phrase.remove_note(position, note);
+                    moved.push((position, length, note));
+                }
+            }
+            staves[stave].retain(|p| p.num_elements() > 0);
+
+            for (position, length, mut note) in moved {
+                let home = note.cross_staff.take().unwrap();
+                let mut elements = BTreeMap::new();
+                elements.insert(position, (PhraseElement::Note(note), length));
+                let phrase = Phrase::new(elements);
+                let home_stave = &mut staves[home as usize - 1];
+                match home_stave
+                    .iter_mut()
+                    .find(|p| p.element_at(position).is_some())
+                {
+                    Some(home_phrase) => home_phrase.merge(phrase),
+                    None => home_stave.push(phrase),
+                }
+                cross_staff.push(CrossStaffNote {
+                    stave: home,
+                    start: position,
+                    end: position + length,
+                    value: note.value(),
+                    display: stave as u8 + 1,
+                });
+            }
+        }
+        cross_staff
+    }
+
+    /// Pack the phrases on a stave into as few voices as possible by putting phrases which don't overlap into the same
+    /// voice. Once there are max_voices voices, any other phrases are merged as chords into the voice sharing the most
+    /// onsets with them. The voices are returned highest first.
+    fn pack_voices(mut stave: Vec<Phrase>, max_voices: usize) -> Vec<Phrase> {
+        stave.sort_by_key(|p| p.start());
+        let mut voices: Vec<Phrase> = Vec::new();
+        let mut overflow = Vec::new();
+        for phrase in stave {
+            match voices.iter().position(|voice| !voice.overlaps(&phrase)) {
+                Some(voice) => voices[voice].merge(phrase),
+                None if voices.len() < max_voices.max(1) => voices.push(phrase),
+                None => overflow.push(phrase),
+            }
+        }
+        for phrase in overflow {
+            let mean = phrase.mean() as i32;
+            let voice_means: Vec<i32> = voices.iter().map(|voice| voice.mean() as i32).collect();
+            let voice = (0..voices.len())
+                .min_by_key(|&i| {
+                    let shared = phrase
+                        .elements_ref()
+                        .keys()
+                        .filter(|p| voices[i].elements_ref().contains_key(p))
+                        .count();
+                    (std::cmp::Reverse(shared), (voice_means[i] - mean).abs())
+                })
+                .unwrap();
+            voices[voice].merge(phrase);
+        }
+        voices.sort_by_cached_key(|voice| std::cmp::Reverse(voice.mean()));
+        voices
+    }
+
+    /// Find the runs of at least two empty bars which aren't broken by a change, returning the length of each run at
+    /// the bar it starts in.
+    fn multiple_rests(empty: &[bool], changes: &[bool]) -> Vec<Option<usize>> {
+        let mut rests = vec![None; empty.len()];
+        let mut start = 0;
+        while start < empty.len() {
+            if !empty[start] {
+                start += 1;
+                continue;
+            }
+            let mut end = start + 1;
+            while end < empty.len() && empty[end] && !changes[end] {
+                end += 1;
+            }
+            if end - start > 1 {
+                rests[start] = Some(end - start);
+            }
+            start = end;
+        }
+        rests
+    }
+
+    /// Find the positions on a stave where the parts entering change, along with a label naming the new parts.
+    fn cue_labels(stave: &[Phrase], parts: &[PartInfo]) -> BTreeMap<Fraction, String> {
+        let mut onsets: BTreeMap<Fraction, BTreeSet<usize>> = BTreeMap::new();
+        for phrase in stave {
+            for (position, (element, _)) in phrase.elements_ref() {
+                let sources = element
+                    .notes()
+                    .iter()
+                    .filter(|n| !n.tie.is_stop())
+                    .filter_map(|n| n.source.map(|s| s.part));
+                onsets.entry(*position).or_default().extend(sources);
+            }
+        }
+
+        let mut labels = BTreeMap::new();
+        let mut current = BTreeSet::new();
+        for (position, sources) in onsets {
+            if !sources.is_empty() && sources != current {
+                let label = sources.iter().map(|&p| parts[p].short_name()).join(", ");
+                labels.insert(position, label);
+                current = sources;
+            }
+        }
+        labels
+    }
+
     /// Get the XML bytes.
     pub fn get_value(self) -> Vec<u8> {
         self.xml.get_value()
@@ -167,7 +662,7 @@ impl OutputScore {
 }
 
 /// Used for calculating bar numbers based off time signatures.
-struct BarNumbers {
+pub struct BarNumbers {
     offsets: BTreeMap<Fraction, (usize, (u8, u8))>,
 }
 
@@ -217,8 +712,12 @@ impl BarNumbers {
 
 #[cfg(test)]
 mod tests {
+    use crate::explain::DecisionLog;
     use crate::fraction::Fraction;
-    use crate::output_score::BarNumbers;
+    use crate::output_score::{BarNumbers, OutputOptions, OutputScore};
+    use crate::phrase::Phrase;
+    use crate::phrase_element::{Note, NoteName, PhraseElement, Source, Tie};
+    use crate::score_representation::{PartInfo, StaveList};
     use std::collections::BTreeMap;
 
     fn setup() -> BarNumbers {
@@ -259,4 +758,316 @@ mod tests {
             Some(Fraction::new(19, 1))
         );
     }
+
+    #[test]
+    fn pack_voices() {
+        let phrase = |notes: &[(i32, i32, NoteName)]| {
+            let mut elements = BTreeMap::new();
+            for &(start, length, name) in notes {
+                elements.insert(
+                    Fraction::new(start, 1),
+                    (
+                        PhraseElement::Note(Note::new(name, 4, 0, Tie::None)),
+                        Fraction::new(length, 1),
+                    ),
+                );
+            }
+            Phrase::new(elements)
+        };
+        let stave = vec![
+            phrase(&[(0, 2, NoteName::C)]),
+            phrase(&[(2, 2, NoteName::D)]),
+            phrase(&[(0, 4, NoteName::G)]),
+            phrase(&[(1, 1, NoteName::E)]),
+        ];
+
+        // The two phrases following each other share a voice.
+        let voices = OutputScore::pack_voices(stave.clone(), 4);
+        assert_eq!(voices.len(), 3);
+        assert_eq!(voices[0], phrase(&[(0, 4, NoteName::G)]));
+        assert_eq!(
+            voices[2],
+            phrase(&[(0, 2, NoteName::C), (2, 2, NoteName::D)])
+        );
+
+        // With two voices, the last phrase becomes part of a chord.
+        let voices = OutputScore::pack_voices(stave, 2);
+        assert_eq!(voices.len(), 2);
+        assert_eq!(voices.iter().map(|v| v.num_elements()).sum::<usize>(), 4);
+
+        // Chords of several high notes can be formed and compared once the voices are full.
+        let high = |name| {
+            let mut elements = BTreeMap::new();
+            elements.insert(
+                Fraction::zero(),
+                (
+                    PhraseElement::Note(Note::new(name, 6, 0, Tie::None)),
+                    Fraction::new(4, 1),
+                ),
+            );
+            Phrase::new(elements)
+        };
+        let stave = [
+            NoteName::C,
+            NoteName::D,
+            NoteName::E,
+            NoteName::F,
+            NoteName::G,
+            NoteName::A,
+        ]
+        .iter()
+        .map(|&name| high(name))
+        .collect();
+        let voices = OutputScore::pack_voices(stave, 2);
+        assert_eq!(voices.len(), 2);
+        let chord_sizes: Vec<usize> = voices.iter().map(|v| v.first().0.notes().len()).collect();
+        assert_eq!(chord_sizes.iter().sum::<usize>(), 6);
+        assert!(chord_sizes.iter().any(|&size| size >= 3));
+        assert!(voices[0].mean() >= voices[1].mean());
+    }
+
+    #[test]
+    fn multiple_rests() {
+        let empty = [false, true, true, true, false, true, true, true, true];
+        let mut changes = [false; 9];
+        changes[7] = true;
+        assert_eq!(
+            OutputScore::multiple_rests(&empty, &changes),
+            vec![
+                None,
+                Some(3),
+                None,
+                None,
+                None,
+                Some(2),
+                None,
+                Some(2),
+                None
+            ]
+        );
+
+        // A single empty bar is left as a bar rest.
+        assert_eq!(
+            OutputScore::multiple_rests(&[true, false], &[true, false]),
+            vec![None, None]
+        );
+    }
+
+    #[test]
+    fn cue_labels() {
+        let parts = vec![
+            PartInfo {
+                id: "P1".to_string(),
+                abbreviation: "Fl.".to_string(),
+                ..Default::default()
+            },
+            PartInfo {
+                id: "P2".to_string(),
+                name: "Horn".to_string(),
+                ..Default::default()
+            },
+        ];
+        let note = |part, tie| {
+            let mut note = Note::new(NoteName::C, 4, 0, tie);
+            note.source = Some(Source {
+                part,
+                measure: 1,
+                voice: 1,
+            });
+            PhraseElement::Note(note)
+        };
+        let mut elements = BTreeMap::new();
+        elements.insert(Fraction::zero(), (note(0, Tie::Start), Fraction::new(1, 1)));
+        elements.insert(
+            Fraction::new(1, 1),
+            (note(0, Tie::Stop), Fraction::new(1, 1)),
+        );
+        elements.insert(
+            Fraction::new(2, 1),
+            (note(0, Tie::None), Fraction::new(1, 1)),
+        );
+        elements.insert(
+            Fraction::new(3, 1),
+            (note(1, Tie::None), Fraction::new(1, 1)),
+        );
+        let stave = vec![Phrase::new(elements)];
+
+        let labels = OutputScore::cue_labels(&stave, &parts);
+        let mut expected = BTreeMap::new();
+        expected.insert(Fraction::zero(), "Fl.".to_string());
+        expected.insert(Fraction::new(3, 1), "Horn".to_string());
+        assert_eq!(labels, expected);
+    }
+
+    #[test]
+    fn cross_staff_notes() {
+        let g = Note::new(NoteName::G, 5, 0, Tie::None);
+        let mut c = Note::new(NoteName::C, 4, 0, Tie::None);
+        c.cross_staff = Some(1);
+        let phrase = |note| {
+            let mut elements = BTreeMap::new();
+            elements.insert(
+                Fraction::zero(),
+                (PhraseElement::Note(note), Fraction::new(1, 1)),
+            );
+            Phrase::new(elements)
+        };
+        let mut staves = vec![vec![phrase(g)], vec![phrase(c)]];
+
+        let cross_staff = OutputScore::place_cross_staff_notes(&mut staves);
+        assert_eq!(cross_staff.len(), 1);
+        assert_eq!(
+            (
+                cross_staff[0].stave,
+                cross_staff[0].value,
+                cross_staff[0].display
+            ),
+            (1, c.value(), 2)
+        );
+
+        c.cross_staff = None;
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Chord(vec![g, c]), Fraction::new(1, 1)),
+        );
+        assert_eq!(staves, vec![vec![Phrase::new(elements)], Vec::new()]);
+    }
+
+    #[test]
+    fn empty_staves() {
+        // A bar of 3/4 with a dotted half note on the first of two staves.
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (
+                PhraseElement::Note(Note::new(NoteName::C, 5, 0, Tie::None)),
+                Fraction::new(3, 1),
+            ),
+        );
+        let phrase = Phrase::new(elements);
+        let mut times = BTreeMap::new();
+        times.insert(Fraction::zero(), (3, 4));
+        let mut keys = BTreeMap::new();
+        keys.insert(Fraction::zero(), 0);
+        let stave_list = StaveList {
+            staves: vec![vec![phrase], Vec::new()],
+            parts: Vec::new(),
+            keys,
+            times,
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+
+        let xml = OutputScore::new(stave_list, &OutputOptions::default()).get_value();
+        let text = String::from_utf8(xml).unwrap();
+        let doc = roxmltree::Document::parse(&text).unwrap();
+        let measures: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("measure"))
+            .collect();
+        assert_eq!(measures.len(), 1);
+        let bar_rests: Vec<(String, String)> = measures[0]
+            .descendants()
+            .filter(|n| n.has_tag_name("rest") && n.attribute("measure") == Some("yes"))
+            .map(|rest| {
+                let note = rest.parent().unwrap();
+                let child = |name| {
+                    note.children()
+                        .find(|n| n.has_tag_name(name))
+                        .and_then(|n| n.text())
+                        .unwrap()
+                        .to_string()
+                };
+                (child("staff"), child("duration"))
+            })
+            .collect();
+        // The divisions are in quarters, so the rest fills the three beats of the bar.
+        assert_eq!(bar_rests, vec![("2".to_string(), "3".to_string())]);
+    }
+
+    #[test]
+    fn stems() {
+        // A bar of 2/4 with a quarter note above the middle line and two beamed eighths below it.
+        let mut elements = BTreeMap::new();
+        for (position, length, name, octave) in [
+            ((0, 1), (1, 1), NoteName::C, 5),
+            ((1, 1), (1, 2), NoteName::E, 4),
+            ((3, 2), (1, 2), NoteName::B, 4),
+        ] {
+            elements.insert(
+                Fraction::new(position.0, position.1),
+                (
+                    PhraseElement::Note(Note::new(name, octave, 0, Tie::None)),
+                    Fraction::new(length.0, length.1),
+                ),
+            );
+        }
+        let mut times = BTreeMap::new();
+        times.insert(Fraction::zero(), (2, 4));
+        let stave_list = StaveList {
+            staves: vec![vec![Phrase::new(elements)]],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times,
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+
+        let xml = OutputScore::new(stave_list, &OutputOptions::default()).get_value();
+        let text = String::from_utf8(xml).unwrap();
+        let doc = roxmltree::Document::parse(&text).unwrap();
+        let stems: Vec<&str> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("stem"))
+            .filter_map(|n| n.text())
+            .collect();
+        // The beamed eighths follow the E, which is further from the middle line than the B on it.
+        assert_eq!(stems, vec!["down", "up", "up"]);
+    }
+
+    #[test]
+    fn tuplets() {
+        // A bar of 2/4 with two triplet eighths and a triplet eighth rest, then a quarter note.
+        let mut elements = BTreeMap::new();
+        for (position, length, name) in [
+            ((0, 1), (1, 3), NoteName::C),
+            ((1, 3), (1, 3), NoteName::D),
+            ((1, 1), (1, 1), NoteName::E),
+        ] {
+            elements.insert(
+                Fraction::new(position.0, position.1),
+                (
+                    PhraseElement::Note(Note::new(name, 5, 0, Tie::None)),
+                    Fraction::new(length.0, length.1),
+                ),
+            );
+        }
+        let mut times = BTreeMap::new();
+        times.insert(Fraction::zero(), (2, 4));
+        let stave_list = StaveList {
+            staves: vec![vec![Phrase::new(elements)]],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times,
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+
+        let xml = OutputScore::new(stave_list, &OutputOptions::default()).get_value();
+        let text = String::from_utf8(xml).unwrap();
+        let doc = roxmltree::Document::parse(&text).unwrap();
+        let tuplets: Vec<Vec<&str>> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("note"))
+            .map(|note| {
+                note.descendants()
+                    .filter(|n| n.has_tag_name("tuplet"))
+                    .filter_map(|n| n.attribute("type"))
+                    .collect()
+            })
+            .collect();
+        // The bracket covers the two notes and the rest, which together fill a quarter.
+        assert_eq!(tuplets, vec![vec!["start"], vec![], vec!["stop"], vec![]]);
+    }
 }
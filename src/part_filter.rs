@@ -0,0 +1,142 @@
+use crate::score_representation::PartInfo;
+
+/// Selects which parts of the source score are reduced, and how important each of them is.
+///
+/// Parts are matched by id or name ignoring case, using patterns in which `*` matches any run of characters and `?`
+/// matches any single character. If any parts are included, only those parts are reduced. Excluded parts are never
+/// reduced.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PartFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    weights: Vec<(String, f64)>,
+}
+
+impl PartFilter {
+    /// Include the parts matching a pattern.
+    pub fn include(&mut self, pattern: &str) {
+        self.include.push(pattern.to_string());
+    }
+
+    /// Exclude the parts matching a pattern.
+    pub fn exclude(&mut self, pattern: &str) {
+        self.exclude.push(pattern.to_string());
+    }
+
+    /// Parse a weight of the form `pattern = weight` and give it to the parts matching the pattern.
+    pub fn add_weight(&mut self, weight: &str) -> Result<(), String> {
+        let (pattern, value) = match weight.rfind('=') {
+            Some(index) => (weight[..index].trim(), weight[index + 1..].trim()),
+            None => return Err("expected `part = weight`".to_string()),
+        };
+        if pattern.is_empty() {
+            return Err("missing part id or name".to_string());
+        }
+        let value: f64 = value
+            .parse()
+            .ok()
+            .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| "weight must be a number greater than or equal to 0".to_string())?;
+        self.weights.push((pattern.to_string(), value));
+        Ok(())
+    }
+
+    /// Check whether a part should be reduced.
+    pub fn selects(&self, part: &PartInfo) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| Self::matches(p, part)))
+            && !self.exclude.iter().any(|p| Self::matches(p, part))
+    }
+
+    /// Get the weight of a part, using the first weight whose pattern matches it. Parts without a weight have a weight
+    /// of 1.
+    pub fn weight(&self, part: &PartInfo) -> f64 {
+        self.weights
+            .iter()
+            .find(|(p, _)| Self::matches(p, part))
+            .map(|(_, weight)| *weight)
+            .unwrap_or(1.0)
+    }
+
+    /// Check whether a pattern matches the id or name of a part.
+    fn matches(pattern: &str, part: &PartInfo) -> bool {
+        glob(pattern, &part.id) || glob(pattern, &part.name)
+    }
+}
+
+/// Match text against a pattern ignoring case, where `*` matches any run of characters and `?` matches any single
+/// character.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // The position of the last star in the pattern, and the position in the text it has matched up to.
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the star match one more character and try again.
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::part_filter::{glob, PartFilter};
+    use crate::score_representation::PartInfo;
+
+    fn part(id: &str, name: &str) -> PartInfo {
+        PartInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_match() {
+        assert!(glob("violin*", "Violin II"));
+        assert!(glob("*horn*", "English Horn"));
+        assert!(glob("P?", "P1"));
+        assert!(!glob("P?", "P10"));
+        assert!(glob("*", ""));
+        assert!(!glob("timpani", "Timpani 2"));
+    }
+
+    #[test]
+    fn filter_selects() {
+        let mut filter = PartFilter::default();
+        assert!(filter.selects(&part("P1", "Flute")));
+
+        filter.include("violin*");
+        filter.include("P3");
+        filter.exclude("violin ii");
+        assert!(filter.selects(&part("P1", "Violin I")));
+        assert!(!filter.selects(&part("P2", "Violin II")));
+        assert!(filter.selects(&part("P3", "Viola")));
+        assert!(!filter.selects(&part("P4", "Cello")));
+    }
+
+    #[test]
+    fn filter_weight() {
+        let mut filter = PartFilter::default();
+        filter.add_weight("Oboe = 2").unwrap();
+        filter.add_weight("* = 0.5").unwrap();
+        assert_eq!(filter.weight(&part("P1", "Oboe")), 2.0);
+        assert_eq!(filter.weight(&part("P2", "Flute")), 0.5);
+
+        assert!(filter.add_weight("Oboe").is_err());
+        assert!(filter.add_weight("Oboe = -1").is_err());
+        assert!(filter.add_weight("= 1").is_err());
+    }
+}
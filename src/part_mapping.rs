@@ -0,0 +1,142 @@
+use crate::score_representation::PartInfo;
+
+/// Specifies which stave a part should be placed on, and how many octaves to shift it by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingEntry {
+    pub part: String,
+    pub stave: u8,
+    pub octaves: i8,
+}
+
+/// A user specified mapping of parts to output staves.
+///
+/// Each line of a mapping file has the form `part = stave [octaves]`, where `part` is either a part id or a part name,
+/// `stave` is the output stave counting from 1 at the top, and `octaves` is an optional octave shift. Blank lines and
+/// lines starting with `#` are ignored.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PartMapping {
+    entries: Vec<MappingEntry>,
+}
+
+impl PartMapping {
+    /// Parse a mapping from the text of a mapping file, checking that every stave exists.
+    pub fn parse(text: &str, staves: u8) -> Result<PartMapping, String> {
+        let mut entries = Vec::new();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", line_num + 1, message);
+
+            let (part, values) = match line.rfind('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => return Err(error("expected `part = stave [octaves]`")),
+            };
+            if part.is_empty() {
+                return Err(error("missing part id or name"));
+            }
+
+            let mut values = values.split_whitespace();
+            let stave: u8 = values
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| error("stave must be an integer"))?;
+            if stave == 0 || stave > staves {
+                return Err(error(&format!("stave must be between 1 and {}", staves)));
+            }
+            let octaves: i8 = match values.next() {
+                Some(v) => v
+                    .parse()
+                    .map_err(|_| error("octave shift must be an integer"))?,
+                None => 0,
+            };
+            if values.next().is_some() {
+                return Err(error("unexpected values after octave shift"));
+            }
+
+            entries.push(MappingEntry {
+                part: part.to_string(),
+                stave,
+                octaves,
+            });
+        }
+        Ok(PartMapping { entries })
+    }
+
+    /// Get the mapping entry for a part, matching on the part id first and then the part name.
+    pub fn get(&self, part: &PartInfo) -> Option<&MappingEntry> {
+        self.entries.iter().find(|e| e.part == part.id).or_else(|| {
+            self.entries
+                .iter()
+                .find(|e| e.part.eq_ignore_ascii_case(&part.name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::part_mapping::{MappingEntry, PartMapping};
+    use crate::score_representation::PartInfo;
+
+    #[test]
+    fn mapping_parse() {
+        let text = "# strings in the left hand\nP1 = 1\n\nDouble Bass = 2 -1\n";
+        let mapping = PartMapping::parse(text, 2).unwrap();
+        assert_eq!(
+            mapping.entries,
+            vec![
+                MappingEntry {
+                    part: "P1".to_string(),
+                    stave: 1,
+                    octaves: 0,
+                },
+                MappingEntry {
+                    part: "Double Bass".to_string(),
+                    stave: 2,
+                    octaves: -1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mapping_parse_errors() {
+        assert!(PartMapping::parse("P1 1", 2).is_err());
+        assert!(PartMapping::parse("P1 = 3", 2).is_err());
+        assert!(PartMapping::parse("P1 = 0", 2).is_err());
+        assert!(PartMapping::parse("P1 = 1 up", 2).is_err());
+        assert!(PartMapping::parse("= 1", 2).is_err());
+    }
+
+    #[test]
+    fn mapping_get() {
+        let mapping = PartMapping::parse("P2 = 1\nviolin i = 2 1", 2).unwrap();
+        let part = PartInfo {
+            id: "P1".to_string(),
+            name: "Violin I".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            mapping.get(&part).map(|e| (e.stave, e.octaves)),
+            Some((2, 1))
+        );
+
+        let part = PartInfo {
+            id: "P2".to_string(),
+            name: "Violin II".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            mapping.get(&part).map(|e| (e.stave, e.octaves)),
+            Some((1, 0))
+        );
+
+        let part = PartInfo {
+            id: "P3".to_string(),
+            name: "Viola".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(mapping.get(&part), None);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::fraction::Fraction;
+use std::fmt::{Display, Formatter};
 
 /// Elements of a phrase, contains either a note or a chord.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -86,6 +87,24 @@ impl PhraseElement {
         }
     }
 
+    /// Remove the note of the same pitch as the given one from the element, returning whether any notes remain.
+    pub fn remove_note(&mut self, note: &Note) -> bool {
+        match self {
+            PhraseElement::Note(n) => !n.pitch_equals(note),
+            PhraseElement::Chord(c) => {
+                c.retain(|n| !n.pitch_equals(note));
+                match c.as_slice() {
+                    [] => false,
+                    [n] => {
+                        *self = PhraseElement::Note(*n);
+                        true
+                    }
+                    _ => true,
+                }
+            }
+        }
+    }
+
     /// Add a start tie to every note in the element.
     pub fn start_tie(&mut self) {
         match self {
@@ -110,13 +129,29 @@ impl PhraseElement {
         }
     }
 
+    /// Get the notes in the element.
+    pub fn notes(&self) -> &[Note] {
+        match self {
+            PhraseElement::Note(n) => std::slice::from_ref(n),
+            PhraseElement::Chord(c) => c.as_slice(),
+        }
+    }
+
+    /// Get mutable access to the notes in an element.
+    pub fn notes_mut(&mut self) -> &mut [Note] {
+        match self {
+            PhraseElement::Note(n) => std::slice::from_mut(n),
+            PhraseElement::Chord(c) => c.as_mut_slice(),
+        }
+    }
+
     /// Get the total value and number of notes in an element which can be used to calculate the mean.
-    pub fn mean(&self) -> (u8, u8) {
+    pub fn mean(&self) -> (u32, u32) {
         match self {
-            PhraseElement::Note(n) => (n.value(), 1),
+            PhraseElement::Note(n) => (n.value() as u32, 1),
             PhraseElement::Chord(c) => (
-                c.iter().fold(0, |sum, note| sum + note.value()),
-                c.len() as u8,
+                c.iter().fold(0, |sum, note| sum + note.value() as u32),
+                c.len() as u32,
             ),
         }
     }
@@ -148,6 +183,26 @@ impl PhraseElement {
             }
         }
     }
+
+    /// Move the staves referred to by any cross staff notes down by a number of staves.
+    pub fn shift_cross_staff(&mut self, staves: u8) {
+        for n in self.notes_mut() {
+            if let Some(home) = &mut n.cross_staff {
+                *home += staves;
+            }
+        }
+    }
+}
+
+/// Defines where a note came from in the source score.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Source {
+    /// The index of the part in the part list.
+    pub part: usize,
+    /// The number of the measure the note started in.
+    pub measure: u32,
+    /// The voice the note was in within its part.
+    pub voice: u8,
 }
 
 /// Defines a note.
@@ -157,6 +212,9 @@ pub struct Note {
     pub octave: u8,
     pub alter: i8,
     pub tie: Tie,
+    pub source: Option<Source>,
+    /// The stave whose voice the note belongs to, if it is played by the hand of another stave.
+    pub cross_staff: Option<u8>,
 }
 
 impl Note {
@@ -167,6 +225,8 @@ impl Note {
             octave,
             alter,
             tie,
+            source: None,
+            cross_staff: None,
         }
     }
 
@@ -176,6 +236,11 @@ impl Note {
         (self.step.value() as i8 + alter) as u8
     }
 
+    /// Get the number of lines and spaces the note is written above C0.
+    pub fn staff_position(&self) -> i32 {
+        self.octave as i32 * 7 + self.step as i32
+    }
+
     /// Check if the other note has the same pitch as this note.
     pub fn pitch_equals(&self, other: &Note) -> bool {
         self.alter == other.alter && self.octave == other.octave && self.step == other.step
@@ -198,6 +263,17 @@ impl Note {
     }
 }
 
+impl Display for Note {
+    /// Display the pitch of a note, such as `F#4`.
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let accidental = match self.alter {
+            alter if alter > 0 => "#".repeat(alter as usize),
+            alter => "b".repeat(-alter as usize),
+        };
+        write!(f, "{}{}{}", self.step.name(), accidental, self.octave)
+    }
+}
+
 /// Defines a tie.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Tie {
@@ -329,47 +405,6 @@ pub enum NoteType {
 }
 
 impl NoteType {
-    /// Get the bar divisions given the smallest note.
-    pub fn to_divisions(&self) -> u32 {
-        match self {
-            NoteType::N1024th => 256,
-            NoteType::N512th => 128,
-            NoteType::N256th => 64,
-            NoteType::N128th => 32,
-            NoteType::N64th => 16,
-            NoteType::N32nd => 8,
-            NoteType::N16th => 4,
-            NoteType::Eighth => 2,
-            NoteType::Quarter => 1,
-            NoteType::Half => 1,
-            NoteType::Whole => 1,
-            NoteType::Breve => 1,
-            NoteType::Long => 1,
-            NoteType::Maxima => 1,
-        }
-    }
-
-    /// Get the length of a note in divisions given the bar divisions.
-    pub fn divisions(&self, current: u32) -> u32 {
-        let division = match self {
-            NoteType::N1024th => 8192,
-            NoteType::N512th => 4096,
-            NoteType::N256th => 2048,
-            NoteType::N128th => 1024,
-            NoteType::N64th => 512,
-            NoteType::N32nd => 256,
-            NoteType::N16th => 128,
-            NoteType::Eighth => 64,
-            NoteType::Quarter => 32,
-            NoteType::Half => 16,
-            NoteType::Whole => 8,
-            NoteType::Breve => 4,
-            NoteType::Long => 2,
-            NoteType::Maxima => 1,
-        };
-        (current * 32) / division
-    }
-
     /// Get the musicXML name of the note.
     pub fn name(&self) -> &str {
         match self {
@@ -431,6 +466,11 @@ impl NoteType {
         }
     }
 
+    /// Get the number of beams or flags on a note of this type.
+    pub fn beams(&self) -> u8 {
+        (NoteType::Quarter as u8).saturating_sub(*self as u8)
+    }
+
     /// Get a note type from its length in bar divisions.
     fn from_duration(duration: u32, divisions: u32) -> Option<NoteType> {
         let balanced = Fraction::balance(duration as i32, divisions as i32);
@@ -453,12 +493,12 @@ impl NoteType {
         })
     }
 
-    /// Get a list of note types from a fraction
-    pub fn from_fraction(mut fraction: Fraction) -> Vec<NoteType> {
+    /// Get a list of note types from a fraction, along with whatever remains which is too short to be written as one.
+    pub fn from_fraction(mut fraction: Fraction) -> (Vec<NoteType>, Fraction) {
         debug_assert!(fraction >= Fraction::zero());
         let mut notes = Vec::new();
         let mut chunk = Fraction::new(32, 1);
-        while !fraction.is_zero() {
+        while !fraction.is_zero() && chunk >= NoteType::N1024th.get_value() {
             if chunk <= fraction {
                 fraction -= chunk;
                 notes.push(
@@ -468,7 +508,129 @@ impl NoteType {
             }
             chunk /= Fraction::new(2, 1);
         }
-        notes
+        (notes, fraction)
+    }
+}
+
+/// Defines the written length of a note, as a note type with up to two dots, played as part of a tuplet if its length
+/// isn't a power of two.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct NoteLength {
+    pub note_type: NoteType,
+    pub dots: u8,
+    /// The number of notes played in the time of the normal number of notes, such as (3, 2) for a triplet.
+    pub tuplet: Option<(u32, u32)>,
+}
+
+impl NoteLength {
+    /// Create a note length.
+    pub fn new(note_type: NoteType, dots: u8) -> Self {
+        NoteLength {
+            note_type,
+            dots,
+            tuplet: None,
+        }
+    }
+
+    /// Get the note length of a fraction, if it can be written as a single note with at most two dots. Lengths whose
+    /// denominator has an odd factor are written as part of a tuplet of that many notes.
+    pub fn from_fraction(fraction: Fraction) -> Option<NoteLength> {
+        let denominator = fraction.denominator();
+        let actual = denominator >> denominator.trailing_zeros();
+        if actual == 1 {
+            return Self::from_dyadic(fraction);
+        }
+        let normal = 1 << (31 - actual.leading_zeros());
+        Self::from_dyadic(fraction * Fraction::new(actual, normal)).map(|length| NoteLength {
+            tuplet: Some((actual as u32, normal as u32)),
+            ..length
+        })
+    }
+
+    /// Get the note length of a fraction whose denominator is a power of two.
+    fn from_dyadic(fraction: Fraction) -> Option<NoteLength> {
+        (0..=2).find_map(|dots| {
+            let base = fraction * Fraction::new(1 << dots, (2 << dots) - 1);
+            NoteType::from_duration(base.numerator() as u32, base.denominator() as u32)
+                .map(|note_type| NoteLength::new(note_type, dots as u8))
+        })
+    }
+
+    /// Get the value of the note length as a fraction.
+    pub fn get_value(&self) -> Fraction {
+        let dots = self.dots as i32;
+        let value = self.note_type.get_value() * Fraction::new((2 << dots) - 1, 1 << dots);
+        match self.tuplet {
+            Some((actual, normal)) => value * Fraction::new(normal as i32, actual as i32),
+            None => value,
+        }
+    }
+}
+
+impl From<NoteType> for NoteLength {
+    fn from(note_type: NoteType) -> Self {
+        NoteLength::new(note_type, 0)
+    }
+}
+
+/// Defines the direction of a stem.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stem {
+    Up,
+    Down,
+}
+
+impl Stem {
+    /// Get the MusicXML name of a stem direction.
+    pub fn name(&self) -> &str {
+        match self {
+            Stem::Up => "up",
+            Stem::Down => "down",
+        }
+    }
+}
+
+/// Defines how a beam continues from a note.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Beam {
+    Begin,
+    Continue,
+    End,
+    ForwardHook,
+    BackwardHook,
+}
+
+impl Beam {
+    /// Get the MusicXML name of a beam.
+    pub fn name(&self) -> &str {
+        match self {
+            Beam::Begin => "begin",
+            Beam::Continue => "continue",
+            Beam::End => "end",
+            Beam::ForwardHook => "forward hook",
+            Beam::BackwardHook => "backward hook",
+        }
+    }
+}
+
+/// Defines an accidental written before a note, which is cautionary if the note would have the same alteration
+/// without it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Accidental {
+    pub alter: i8,
+    pub cautionary: bool,
+}
+
+impl Accidental {
+    /// Get the MusicXML name of an accidental.
+    pub fn name(&self) -> &str {
+        match self.alter {
+            alter if alter <= -2 => "flat-flat",
+            -1 => "flat",
+            0 => "natural",
+            1 => "sharp",
+            _ => "double-sharp",
+        }
     }
 }
 
@@ -495,12 +657,20 @@ impl Clef {
             Clef::Bass => "4",
         }
     }
+
+    /// Get the staff position of the middle line of a stave with this clef.
+    pub fn middle_line(&self) -> i32 {
+        match self {
+            Clef::Treble => Note::new(NoteName::B, 4, 0, Tie::None).staff_position(),
+            Clef::Bass => Note::new(NoteName::D, 3, 0, Tie::None).staff_position(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::fraction::Fraction;
-    use crate::phrase_element::{Note, NoteName, NoteType, Tie};
+    use crate::phrase_element::{Note, NoteLength, NoteName, NoteType, PhraseElement, Tie};
 
     #[test]
     fn note_values() {
@@ -514,18 +684,85 @@ mod tests {
         assert_eq!(note.value(), 49);
     }
 
+    #[test]
+    fn note_display() {
+        let note = Note::new(NoteName::F, 4, 1, Tie::None);
+        assert_eq!(note.to_string(), "F#4");
+
+        let note = Note::new(NoteName::B, 3, -2, Tie::None);
+        assert_eq!(note.to_string(), "Bbb3");
+    }
+
+    #[test]
+    fn chord_mean() {
+        // The pitches of a high chord add up to more than fits in a byte.
+        let chord = PhraseElement::Chord(vec![
+            Note::new(NoteName::C, 7, 0, Tie::None),
+            Note::new(NoteName::E, 7, 0, Tie::None),
+            Note::new(NoteName::G, 7, 0, Tie::None),
+        ]);
+        assert_eq!(chord.mean(), (263, 3));
+    }
+
     #[test]
     fn note_types_from_fraction() {
         let duration = Fraction::new(1, 1);
-        assert_eq!(NoteType::from_fraction(duration), vec![NoteType::Quarter]);
+        assert_eq!(
+            NoteType::from_fraction(duration),
+            (vec![NoteType::Quarter], Fraction::zero())
+        );
 
         let duration = Fraction::new(3, 2);
         assert_eq!(
             NoteType::from_fraction(duration),
-            vec![NoteType::Quarter, NoteType::Eighth]
+            (vec![NoteType::Quarter, NoteType::Eighth], Fraction::zero())
         );
 
         let duration = Fraction::zero();
-        assert_eq!(NoteType::from_fraction(duration), vec![]);
+        assert_eq!(
+            NoteType::from_fraction(duration),
+            (vec![], Fraction::zero())
+        );
+
+        // A third of a quarter can't be written exactly with note types.
+        let (note_types, remainder) = NoteType::from_fraction(Fraction::new(1, 3));
+        assert_eq!(note_types.len(), 4);
+        assert_eq!(remainder, Fraction::new(1, 768));
+    }
+
+    #[test]
+    fn note_length_from_fraction() {
+        assert_eq!(
+            NoteLength::from_fraction(Fraction::new(1, 1)),
+            Some(NoteLength::new(NoteType::Quarter, 0))
+        );
+        assert_eq!(
+            NoteLength::from_fraction(Fraction::new(3, 2)),
+            Some(NoteLength::new(NoteType::Quarter, 1))
+        );
+        assert_eq!(
+            NoteLength::from_fraction(Fraction::new(7, 2)),
+            Some(NoteLength::new(NoteType::Half, 2))
+        );
+        assert_eq!(NoteLength::from_fraction(Fraction::new(5, 4)), None);
+        assert_eq!(NoteLength::from_fraction(Fraction::zero()), None);
+
+        assert_eq!(
+            NoteLength::from_fraction(Fraction::new(1, 3)),
+            Some(NoteLength {
+                note_type: NoteType::Eighth,
+                dots: 0,
+                tuplet: Some((3, 2)),
+            })
+        );
+        assert_eq!(
+            NoteLength::from_fraction(Fraction::new(1, 5)).map(|l| (l.note_type, l.tuplet)),
+            Some((NoteType::N16th, Some((5, 4))))
+        );
+
+        let length = NoteLength::new(NoteType::Eighth, 2);
+        assert_eq!(length.get_value(), Fraction::new(7, 8));
+        let length = NoteLength::from_fraction(Fraction::new(2, 3)).unwrap();
+        assert_eq!(length.get_value(), Fraction::new(2, 3));
     }
 }
@@ -0,0 +1,152 @@
+use crate::hand_model::HandModel;
+use crate::part_mapping::PartMapping;
+use crate::score_representation::{PhraseList, StaveList};
+
+/// Defines how phrases are allocated to staves.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Strategy {
+    DistributeStaves,
+    MergeByAverage,
+    GroupFamilies,
+}
+
+impl Strategy {
+    /// Get the name of the strategy.
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::DistributeStaves => "distribute",
+            Strategy::MergeByAverage => "average",
+            Strategy::GroupFamilies => "families",
+        }
+    }
+}
+
+/// The options controlling how a PhraseList is reduced to a StaveList.
+#[derive(Debug, Clone)]
+pub struct Reduction {
+    pub staves: u8,
+    pub hand: HandModel,
+    pub strategy: Strategy,
+    /// Place parts onto staves with a user specified mapping. Not used with Merge By Average.
+    pub part_mapping: Option<PartMapping>,
+    pub remove_doublings: bool,
+    pub keep_octaves: bool,
+    pub adjust_octaves: bool,
+    pub merge: bool,
+    /// Merge into up to two rhythmically independent voices on each stave instead of one.
+    pub merge_voices: bool,
+    pub repair_chords: bool,
+}
+
+impl Reduction {
+    /// Reduce the phrases onto staves.
+    pub fn reduce(&self, mut phrase_list: PhraseList) -> StaveList {
+        if self.remove_doublings {
+            phrase_list.remove_doublings(self.keep_octaves);
+        }
+        let stave_list = if self.strategy == Strategy::MergeByAverage {
+            phrase_list.merge_by_average(self.staves)
+        } else {
+            let mut stave_list = match &self.part_mapping {
+                Some(part_mapping) => phrase_list.map_staves(self.staves, part_mapping),
+                None if self.strategy == Strategy::GroupFamilies => {
+                    phrase_list.group_families(self.staves)
+                }
+                None => phrase_list.distribute_staves(self.staves),
+            };
+            if self.adjust_octaves {
+                stave_list.adjust_octaves(&self.hand, self.repair_chords);
+            }
+            stave_list
+        };
+
+        let mut stave_list = if self.merge && self.merge_voices {
+            stave_list.merge_voices()
+        } else if self.merge {
+            stave_list.merge()
+        } else {
+            stave_list
+        };
+        if self.repair_chords {
+            stave_list.repair_chords(&self.hand);
+        }
+        stave_list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hand_model::HandModel;
+    use crate::phrase_element::PhraseElement;
+    use crate::reduction::{Reduction, Strategy};
+    use crate::score_representation::ScoreParser;
+
+    /// A bar with two parts in unison on C5 above a G2.
+    const SCORE: &str = r#"<score-partwise>
+  <part-list>
+    <score-part id="P1"><part-name>Flute</part-name></score-part>
+    <score-part id="P2"><part-name>Oboe</part-name></score-part>
+    <score-part id="P3"><part-name>Cello</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>1</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>C</step><octave>5</octave></pitch><duration>4</duration><voice>1</voice></note>
+    </measure>
+  </part>
+  <part id="P2">
+    <measure number="1">
+      <attributes><divisions>1</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>C</step><octave>5</octave></pitch><duration>4</duration><voice>1</voice></note>
+    </measure>
+  </part>
+  <part id="P3">
+    <measure number="1">
+      <attributes><divisions>1</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>G</step><octave>2</octave></pitch><duration>4</duration><voice>1</voice></note>
+    </measure>
+  </part>
+</score-partwise>"#;
+
+    #[test]
+    fn reduce() {
+        let doc = roxmltree::Document::parse(SCORE).unwrap();
+        let mut parser = ScoreParser::new(doc);
+        let mut reduction = Reduction {
+            staves: 2,
+            hand: HandModel::new(12, 5),
+            strategy: Strategy::DistributeStaves,
+            part_mapping: None,
+            remove_doublings: false,
+            keep_octaves: false,
+            adjust_octaves: true,
+            merge: false,
+            merge_voices: false,
+            repair_chords: false,
+        };
+        // Without pruning or merging, the unison stays as two phrases on the upper stave.
+        let stave_list = reduction.reduce(parser.parse_score(0));
+        let lengths: Vec<usize> = stave_list.staves.iter().map(|s| s.len()).collect();
+        assert_eq!(lengths, vec![2, 1]);
+
+        // The doubling is removed and each stave merged into one phrase.
+        reduction.remove_doublings = true;
+        reduction.merge = true;
+        let stave_list = reduction.reduce(parser.parse_score(0));
+        let notes: Vec<Vec<(u8, Option<usize>)>> = stave_list
+            .staves
+            .iter()
+            .map(|stave| {
+                stave
+                    .iter()
+                    .flat_map(|phrase| phrase.elements_ref().values())
+                    .map(|(element, _)| match element {
+                        PhraseElement::Note(note) => (note.value(), note.source.map(|s| s.part)),
+                        PhraseElement::Chord(_) => panic!("unexpected chord"),
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(notes, vec![vec![(60, Some(0))], vec![(31, Some(2))]]);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::explain::Action;
+use crate::fraction::Fraction;
+use crate::hand_model::HandModel;
+use crate::harmony::HarmonicFidelity;
+use crate::output_score::BarNumbers;
+use crate::phrase::Phrase;
+use crate::phrase_element::Note;
+use crate::score_representation::StaveList;
+use itertools::Itertools;
+use std::collections::BTreeSet;
+
+/// The number of notes from a source part before and after the reduction.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PartCount {
+    pub name: String,
+    pub notes_in: usize,
+    pub notes_out: usize,
+    pub dropped: usize,
+}
+
+/// A summary of how playable a reduction is and how much of the source score it keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub parts: Vec<PartCount>,
+    /// The number of positions in the source score at which a note starts.
+    pub onsets_in: usize,
+    /// The number of those positions at which a note still starts after the reduction.
+    pub onsets_kept: usize,
+    /// The bars on each stave containing a chord which is too wide or has too many notes for the hand.
+    pub violations: Vec<BTreeSet<usize>>,
+    /// The largest number of notes sounding at once on each stave.
+    pub max_polyphony: Vec<usize>,
+    /// The number of phrases transposed by an octave to fit the hand.
+    pub transpositions: usize,
+    /// The number of notes dropped because they couldn't be played.
+    pub dropped: usize,
+    pub harmony: HarmonicFidelity,
+}
+
+impl Report {
+    /// Build a report by comparing the phrases of the source score with the staves of the reduction.
+    pub fn new(original: &[Phrase], stave_list: &StaveList, hand: &HandModel) -> Self {
+        let mut parts: Vec<PartCount> = stave_list
+            .parts
+            .iter()
+            .map(|part| PartCount {
+                name: if part.name.is_empty() {
+                    part.id.clone()
+                } else {
+                    format!("{} ({})", part.name, part.id)
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        let onsets_in = Self::onsets(original.iter());
+        for note in Self::notes(original.iter()) {
+            if let Some(count) = note.source.and_then(|s| parts.get_mut(s.part)) {
+                count.notes_in += 1;
+            }
+        }
+        let onsets_out = Self::onsets(stave_list.staves.iter().flatten());
+        for note in Self::notes(stave_list.staves.iter().flatten()) {
+            if let Some(count) = note.source.and_then(|s| parts.get_mut(s.part)) {
+                count.notes_out += 1;
+            }
+        }
+        for dropped in &stave_list.dropped {
+            let source = dropped.phrase.source();
+            if let Some(count) = source.and_then(|s| parts.get_mut(s.part)) {
+                count.dropped += 1;
+            }
+        }
+
+        let bar_numbers = BarNumbers::new(&stave_list.times);
+        let mut violations = Vec::new();
+        let mut max_polyphony = Vec::new();
+        for stave in &stave_list.staves {
+            let mut stave_violations = BTreeSet::new();
+            let mut polyphony = 0;
+            let positions = stave
+                .iter()
+                .map(|p| p.elements_ref().keys().cloned())
+                .kmerge()
+                .unique();
+            for position in positions {
+                let pitches: Vec<u8> = StaveList::notes_at(stave, position)
+                    .iter()
+                    .map(|(_, _, n)| n.value())
+                    .collect();
+                polyphony = polyphony.max(pitches.len());
+                if !hand.can_play(&pitches) {
+                    stave_violations.insert(bar_numbers.get_bar_number(position) + 1);
+                }
+            }
+            violations.push(stave_violations);
+            max_polyphony.push(polyphony);
+        }
+
+        let transpositions = stave_list
+            .log
+            .decisions
+            .iter()
+            .filter(|d| matches!(d.action, Action::Transposed { .. }))
+            .count();
+
+        let harmony = HarmonicFidelity::new(
+            original.iter(),
+            stave_list.staves.iter().flatten(),
+            &stave_list.times,
+        );
+
+        Report {
+            parts,
+            onsets_in: onsets_in.len(),
+            onsets_kept: onsets_in.intersection(&onsets_out).count(),
+            violations,
+            max_polyphony,
+            transpositions,
+            dropped: stave_list.dropped.len(),
+            harmony,
+        }
+    }
+
+    /// Get the notes which start in a set of phrases, leaving out the continuations of tied notes.
+    fn notes<'a>(phrases: impl Iterator<Item = &'a Phrase>) -> impl Iterator<Item = &'a Note> {
+        phrases
+            .flat_map(|phrase| phrase.elements_ref().values())
+            .flat_map(|(element, _)| element.notes())
+            .filter(|note| !note.tie.is_stop())
+    }
+
+    /// Get the positions at which a note starts in a set of phrases.
+    fn onsets<'a>(phrases: impl Iterator<Item = &'a Phrase>) -> BTreeSet<Fraction> {
+        phrases
+            .flat_map(|phrase| phrase.elements_ref())
+            .filter(|(_, (element, _))| element.notes().iter().any(|n| !n.tie.is_stop()))
+            .map(|(position, _)| *position)
+            .collect()
+    }
+
+    /// Number the bars from the first bar of an excerpt instead of from 1.
+    pub fn set_first_bar(&mut self, first_bar: usize) {
+        let offset = first_bar.saturating_sub(1);
+        for violations in &mut self.violations {
+            *violations = violations.iter().map(|bar| bar + offset).collect();
+        }
+        for bar in &mut self.harmony.bars {
+            bar.bar += offset;
+        }
+    }
+
+    /// Get the proportion of onsets in the source score which are kept in the reduction.
+    pub fn onsets_kept_ratio(&self) -> f64 {
+        if self.onsets_in == 0 {
+            1.0
+        } else {
+            self.onsets_kept as f64 / self.onsets_in as f64
+        }
+    }
+
+    /// Get the number of bars with a stretch violation on any stave.
+    pub fn violating_bars(&self) -> usize {
+        self.violations.iter().flatten().unique().count()
+    }
+
+    /// Score the reduction between 0 and 1 by averaging its harmonic similarity, the proportion of onsets it keeps, the
+    /// proportion of notes which weren't dropped and the proportion of bars without stretch violations.
+    pub fn score(&self) -> f64 {
+        let notes_in: usize = self.parts.iter().map(|p| p.notes_in).sum();
+        let kept = if notes_in == 0 {
+            1.0
+        } else {
+            1.0 - self.dropped as f64 / notes_in as f64
+        };
+        let bars = self.harmony.bars.len();
+        let playable = if bars == 0 {
+            1.0
+        } else {
+            1.0 - (self.violating_bars() as f64 / bars as f64).min(1.0)
+        };
+        (self.harmony.overall + self.onsets_kept_ratio() + kept + playable) / 4.0
+    }
+
+    /// Write the report as readable text.
+    pub fn to_text(&self) -> String {
+        let width = self
+            .parts
+            .iter()
+            .map(|p| p.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let mut text = format!(
+            "{:width$}  {:>6}  {:>6}  {:>7}\n",
+            "Part",
+            "In",
+            "Out",
+            "Dropped",
+            width = width
+        );
+        for part in &self.parts {
+            text.push_str(&format!(
+                "{:width$}  {:>6}  {:>6}  {:>7}\n",
+                part.name,
+                part.notes_in,
+                part.notes_out,
+                part.dropped,
+                width = width
+            ));
+        }
+        text.push('\n');
+        text.push_str(&format!(
+            "Onsets kept: {:.1}% ({} of {})\n",
+            self.onsets_kept_ratio() * 100.0,
+            self.onsets_kept,
+            self.onsets_in
+        ));
+        text.push_str(&format!("Octave transpositions: {}\n", self.transpositions));
+        text.push_str(&format!("Dropped notes: {}\n", self.dropped));
+        for (stave, (violations, polyphony)) in
+            self.violations.iter().zip(&self.max_polyphony).enumerate()
+        {
+            let violations = if violations.is_empty() {
+                "no stretch violations".to_string()
+            } else {
+                format!(
+                    "stretch violations in bars {}",
+                    violations.iter().join(", ")
+                )
+            };
+            text.push_str(&format!(
+                "Stave {}: maximum polyphony {}, {}\n",
+                stave + 1,
+                polyphony,
+                violations
+            ));
+        }
+        text.push('\n');
+        text.push_str(&self.harmony.to_text());
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::explain::DecisionLog;
+    use crate::fraction::Fraction;
+    use crate::hand_model::HandModel;
+    use crate::phrase::Phrase;
+    use crate::phrase_element::{Note, NoteName, PhraseElement, Source, Tie};
+    use crate::report::Report;
+    use crate::score_representation::{PartInfo, StaveList};
+    use std::collections::BTreeMap;
+
+    fn phrase(notes: &[(i32, NoteName, u8)]) -> Phrase {
+        let mut elements = BTreeMap::new();
+        for &(position, name, octave) in notes {
+            let mut note = Note::new(name, octave, 0, Tie::None);
+            note.source = Some(Source {
+                part: 0,
+                measure: 1,
+                voice: 1,
+            });
+            elements.insert(
+                Fraction::new(position, 1),
+                (PhraseElement::Note(note), Fraction::new(1, 1)),
+            );
+        }
+        Phrase::new(elements)
+    }
+
+    #[test]
+    fn report() {
+        let original = vec![
+            phrase(&[
+                (0, NoteName::C, 4),
+                (1, NoteName::D, 4),
+                (2, NoteName::E, 4),
+            ]),
+            phrase(&[(0, NoteName::C, 6), (3, NoteName::G, 4)]),
+        ];
+        let mut times = BTreeMap::new();
+        times.insert(Fraction::zero(), (2, 4));
+        let stave_list = StaveList {
+            staves: vec![vec![
+                phrase(&[(0, NoteName::C, 4), (2, NoteName::E, 4)]),
+                phrase(&[(0, NoteName::C, 6), (2, NoteName::G, 4)]),
+            ]],
+            parts: vec![PartInfo {
+                id: "P1".to_string(),
+                name: "Flute".to_string(),
+                ..Default::default()
+            }],
+            keys: BTreeMap::new(),
+            times,
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+        let report = Report::new(&original, &stave_list, &HandModel::new(12, 5));
+
+        assert_eq!(report.parts[0].name, "Flute (P1)");
+        assert_eq!(
+            (report.parts[0].notes_in, report.parts[0].notes_out),
+            (5, 4)
+        );
+        assert_eq!((report.onsets_in, report.onsets_kept), (4, 2));
+        assert_eq!(report.max_polyphony, vec![2]);
+        // The two octave stretch at the start is in the first bar.
+        assert_eq!(report.violations[0].iter().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(report.violating_bars(), 1);
+
+        // The score averages the harmonic similarity of the two bars, the half of the onsets which are kept, the notes
+        // kept as nothing is dropped, and the one of the two bars which can be played.
+        let harmony = (0.5 + 0.5_f64.sqrt() / 2.0) / 2.0;
+        assert!((report.harmony.overall - harmony).abs() < 1e-9);
+        assert_eq!(report.onsets_kept_ratio(), 0.5);
+        assert_eq!(report.dropped, 0);
+        assert_eq!(report.harmony.bars.len(), 2);
+        assert!((report.score() - (harmony + 0.5 + 1.0 + 0.5) / 4.0).abs() < 1e-9);
+
+        // An excerpt starting at bar 30 is numbered from there.
+        let mut report = report;
+        report.set_first_bar(30);
+        assert_eq!(report.violations[0].iter().collect::<Vec<_>>(), vec![&30]);
+        assert!(report.to_text().contains("Bar 30: "));
+        assert!(!report.to_text().contains("Bar 1: "));
+    }
+}
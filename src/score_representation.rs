@@ -1,41 +1,135 @@
+use crate::explain::{Action, Candidates, DecisionLog, MoveReason};
 use crate::fraction::Fraction;
-use crate::phrase::Phrase;
+use crate::hand_model::HandModel;
+use crate::instrument::InstrumentFamily;
+use crate::part_filter::PartFilter;
+use crate::part_mapping::PartMapping;
+use crate::phrase::{Phrase, PitchClasses};
 use crate::phrase_element::*;
 use itertools::Itertools;
 use roxmltree::{Document, Node};
 use std::collections::BTreeMap;
 
+/// The value of middle C.
+const MIDDLE_C: u8 = 48;
+
 /// Parses a MusicXML document to a PhraseList.
 pub struct ScoreParser<'a> {
     doc: Document<'a>,
+    bars: Option<(u32, u32)>,
+    filter: PartFilter,
 }
 
 impl<'a> ScoreParser<'a> {
     /// Construct a musicXML parser with a roxmltree document.
     pub fn new(doc: Document) -> ScoreParser {
-        ScoreParser { doc }
+        ScoreParser {
+            doc,
+            bars: None,
+            filter: PartFilter::default(),
+        }
+    }
+
+    /// Only parse the bars from first to last inclusive, identified by their measure numbers.
+    pub fn set_bars(&mut self, first: u32, last: u32) {
+        self.bars = Some((first, last));
+    }
+
+    /// Only parse the parts selected by a filter, weighting them as it specifies.
+    pub fn set_filter(&mut self, filter: PartFilter) {
+        self.filter = filter;
     }
 
     /// Parse the score.
     pub fn parse_score(&mut self, phrase_limit: u32) -> PhraseList {
         let mut score = PhraseList::new();
         let score_element = self.doc.root_element();
+        if let Some(part_list) = score_element
+            .children()
+            .find(|n| n.has_tag_name("part-list"))
+        {
+            score.parse_part_list(part_list);
+        }
+        let all_parts = score.parts.clone();
+        score.parts.retain(|part| self.filter.selects(part));
         let children = score_element.children();
         let parts = children.filter(|n| n.has_tag_name("part"));
+        let mut excerpt = None;
         for part in parts {
-            score.parse_part(part, phrase_limit);
+            // Parts missing from the part list can still be selected by their id.
+            let id = part.attribute("id").unwrap_or_default();
+            let selected = match all_parts.iter().find(|p| p.id == id) {
+                Some(info) => self.filter.selects(info),
+                None => self.filter.selects(&PartInfo {
+                    id: id.to_string(),
+                    ..Default::default()
+                }),
+            };
+            if !selected {
+                continue;
+            }
+            let part_excerpt = score.parse_part(part, phrase_limit, self.bars);
+            excerpt = excerpt.or(part_excerpt);
+        }
+        if let Some((start, end)) = excerpt {
+            score.excerpt(start, end);
+        }
+        for part in &mut score.parts {
+            part.weight = self.filter.weight(part);
         }
 
         score
     }
 }
 
+/// Information about a part in the source score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartInfo {
+    pub id: String,
+    pub name: String,
+    pub abbreviation: String,
+    pub instruments: Vec<String>,
+    pub groups: Vec<String>,
+    pub family: Option<InstrumentFamily>,
+    /// How important the part is when choosing which doubling or chord notes to keep, where 1 is the default.
+    pub weight: f64,
+}
+
+impl Default for PartInfo {
+    fn default() -> Self {
+        PartInfo {
+            id: String::new(),
+            name: String::new(),
+            abbreviation: String::new(),
+            instruments: Vec::new(),
+            groups: Vec::new(),
+            family: None,
+            weight: 1.0,
+        }
+    }
+}
+
+impl PartInfo {
+    /// Get the abbreviated name of the part, falling back to the full name and then the id.
+    pub fn short_name(&self) -> &str {
+        if !self.abbreviation.is_empty() {
+            &self.abbreviation
+        } else if !self.name.is_empty() {
+            &self.name
+        } else {
+            &self.id
+        }
+    }
+}
+
 /// Defines a list of phrases.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PhraseList {
     phrases: Vec<Phrase>,
+    parts: Vec<PartInfo>,
     keys: BTreeMap<Fraction, i8>,
     times: BTreeMap<Fraction, (u8, u8)>,
+    log: DecisionLog,
 }
 
 
@@ -43,19 +137,126 @@ impl PhraseList {
     fn new() -> PhraseList {
         PhraseList {
             phrases: Vec::new(),
+            parts: Vec::new(),
             keys: BTreeMap::new(),
             times: BTreeMap::new(),
+            log: DecisionLog::default(),
         }
     }
 
-    /// Parse a MusicXML part into a list of phrases.
-    fn parse_part(&mut self, part: Node, phrase_limit: u32) {
+    /// Parse the part list to get the id, name, instruments and groups of each part.
+    fn parse_part_list(&mut self, part_list: Node) {
+        let mut open_groups: Vec<(String, String)> = Vec::new();
+        for child in part_list.children() {
+            if child.has_tag_name("part-group") {
+                let number = child.attribute("number").unwrap_or("1").to_string();
+                match child.attribute("type") {
+                    Some("start") => {
+                        let name = child
+                            .children()
+                            .find(|n| n.has_tag_name("group-name"))
+                            .and_then(|n| n.text())
+                            .unwrap_or_default()
+                            .to_string();
+                        open_groups.push((number, name));
+                    }
+                    Some("stop") => open_groups.retain(|(n, _)| *n != number),
+                    _ => (),
+                }
+            } else if child.has_tag_name("score-part") {
+                let id = child.attribute("id").unwrap_or_default().to_string();
+                let name = child
+                    .children()
+                    .find(|n| n.has_tag_name("part-name"))
+                    .and_then(|n| n.text())
+                    .unwrap_or_default()
+                    .to_string();
+                let abbreviation = child
+                    .children()
+                    .find(|n| n.has_tag_name("part-abbreviation"))
+                    .and_then(|n| n.text())
+                    .unwrap_or_default()
+                    .to_string();
+                let score_instruments = child
+                    .children()
+                    .filter(|n| n.has_tag_name("score-instrument"));
+                let mut instruments = Vec::new();
+                let mut sounds = Vec::new();
+                for score_instrument in score_instruments {
+                    if let Some(instrument_name) = score_instrument
+                        .children()
+                        .find(|n| n.has_tag_name("instrument-name"))
+                        .and_then(|n| n.text())
+                    {
+                        instruments.push(instrument_name.to_string());
+                    }
+                    if let Some(sound) = score_instrument
+                        .children()
+                        .find(|n| n.has_tag_name("instrument-sound"))
+                        .and_then(|n| n.text())
+                    {
+                        sounds.push(sound.to_string());
+                    }
+                }
+                let groups: Vec<String> = open_groups
+                    .iter()
+                    .map(|(_, name)| name.clone())
+                    .filter(|name| !name.is_empty())
+                    .collect();
+
+                // Prefer the instrument sound, then fall back to guessing from the names.
+                let family = sounds
+                    .iter()
+                    .find_map(|s| InstrumentFamily::from_sound(s))
+                    .or_else(|| {
+                        instruments
+                            .iter()
+                            .chain(std::iter::once(&name))
+                            .chain(groups.iter().rev())
+                            .find_map(|n| InstrumentFamily::from_name(n))
+                    });
+
+                self.parts.push(PartInfo {
+                    id,
+                    name,
+                    abbreviation,
+                    instruments,
+                    groups,
+                    family,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    /// Parse a part. If only some bars are being parsed, the start and end positions of those bars are returned.
+    fn parse_part(
+        &mut self,
+        part: Node,
+        phrase_limit: u32,
+        bars: Option<(u32, u32)>,
+    ) -> Option<(Fraction, Fraction)> {
+        let first_phrase = self.phrases.len();
+        let id = part.attribute("id").unwrap_or_default();
+        let part_index = match self.parts.iter().position(|p| p.id == id) {
+            Some(index) => index,
+            None => {
+                self.parts.push(PartInfo {
+                    id: id.to_string(),
+                    ..Default::default()
+                });
+                self.parts.len() - 1
+            }
+        };
         let measures = part.children().filter(|n| n.has_tag_name("measure"));
         let mut divisions: u32 = 0;
         let mut current_pos = Fraction::zero();
         let mut note_list: BTreeMap<Fraction, (PhraseElement, Fraction)> = BTreeMap::new();
         let mut current_transpose = Transpose::new();
+        let mut transposed = false;
         let mut last_bar_num = 0;
+        let mut excerpt_start = None;
+        let mut excerpt_end = None;
         for measure in measures {
             // Parse attribute elements.
             if let Some(attributes) = measure.children().find(|n| n.has_tag_name("attributes")) {
@@ -72,6 +273,15 @@ impl PhraseList {
                 .map(|n| n.parse::<u32>().ok())
                 .flatten()
                 .unwrap_or_default();
+            // Check whether the bar is in the range being parsed.
+            let in_range = bars.is_none_or(|(first, last)| first <= bar_num && bar_num <= last);
+            if bars.is_some() {
+                if in_range && excerpt_start.is_none() {
+                    excerpt_start = Some(current_pos);
+                } else if !in_range && excerpt_start.is_some() && excerpt_end.is_none() {
+                    excerpt_end = Some(current_pos);
+                }
+            }
             // End phrase if longer than phrase limit.
             if phrase_limit > 0 {
                 if bar_num >= last_bar_num + phrase_limit && !note_list.is_empty() {
@@ -129,6 +339,14 @@ impl PhraseList {
 
                 let chord = note.children().find(|n| n.has_tag_name("chord")).is_some();
 
+                // Parse the voice.
+                let voice = note
+                    .children()
+                    .find(|n| n.has_tag_name("voice"))
+                    .and_then(|n| n.text())
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(1);
+
                 // Parse the pitch.
                 let pitch = note.children().find(|n| n.has_tag_name("pitch"));
                 match pitch {
@@ -155,15 +373,24 @@ impl PhraseList {
                             .unwrap();
 
                         let mut note = Note::new(step, octave, alter, tie);
+                        note.source = Some(Source {
+                            part: part_index,
+                            measure: bar_num,
+                            voice,
+                        });
                         current_transpose.apply(&mut note);
+                        transposed |= current_transpose.chromatic != 0;
 
                         // If its a chord, add it to a new phrase.
                         if chord {
                             current_pos -= duration;
-                            let mut note_list = BTreeMap::new();
-                            note_list.insert(current_pos, (PhraseElement::Note(note), duration));
-                            self.phrases.push(Phrase::new(note_list));
-                        } else {
+                            if in_range {
+                                let mut note_list = BTreeMap::new();
+                                note_list
+                                    .insert(current_pos, (PhraseElement::Note(note), duration));
+                                self.phrases.push(Phrase::new(note_list));
+                            }
+                        } else if in_range {
                             note_list.insert(current_pos, (PhraseElement::Note(note), duration));
                         }
                     }
@@ -185,6 +412,49 @@ impl PhraseList {
         if !note_list.is_empty() {
             self.phrases.push(Phrase::new(note_list));
         }
+
+        // Tag the new phrases with the family of the part they came from.
+        let family = self.parts[part_index].family;
+        for phrase in &mut self.phrases[first_phrase..] {
+            phrase.set_family(family);
+        }
+
+        // Notes from a transposing part are respelt in the concert key, as transposing them can leave spellings such
+        // as E sharp or double flats.
+        if transposed {
+            for phrase in &mut self.phrases[first_phrase..] {
+                phrase.respell(&self.keys);
+            }
+        }
+
+        excerpt_start.map(|start| (start, excerpt_end.unwrap_or(current_pos)))
+    }
+
+    /// Cut the score down to the excerpt between two positions, moving it to the start of the score. The key and time
+    /// signature in effect at the start are carried over, and ties into or out of the excerpt are removed.
+    fn excerpt(&mut self, start: Fraction, end: Fraction) {
+        for phrase in &mut self.phrases {
+            phrase.cut_ties(start, end);
+            phrase.offset(-start);
+        }
+        self.keys = Self::excerpt_changes(&self.keys, start, end);
+        self.times = Self::excerpt_changes(&self.times, start, end);
+    }
+
+    /// Get the changes between two positions relative to the start, including the value in effect at the start.
+    fn excerpt_changes<T: Copy>(
+        changes: &BTreeMap<Fraction, T>,
+        start: Fraction,
+        end: Fraction,
+    ) -> BTreeMap<Fraction, T> {
+        let mut excerpt: BTreeMap<Fraction, T> = changes
+            .range(start..end)
+            .map(|(position, value)| (*position - start, *value))
+            .collect();
+        if let Some((_, value)) = changes.range(..=start).next_back() {
+            excerpt.entry(Fraction::zero()).or_insert(*value);
+        }
+        excerpt
     }
 
     /// Parse the measure attributes.
@@ -232,15 +502,27 @@ impl PhraseList {
                 .parse()
                 .unwrap();
 
-            let transposed_key = ((fifths + current_transpose.chromatic * 7) % 12) as i8;
-            let existing = self.keys.insert(current_pos, transposed_key);
-            match existing {
-                Some(n) => {
-                    if transposed_key != n {
+            // Keep the key of a transposing part within six flats and five sharps in concert pitch. Other parts keep
+            // their own spelling of the key, which wins if a transposing part gives its enharmonic equivalent.
+            let transposed = current_transpose.chromatic != 0;
+            let key = if transposed {
+                ((fifths + current_transpose.chromatic * 7 + 6).rem_euclid(12) - 6) as i8
+            } else {
+                fifths as i8
+            };
+            match self.keys.get(&current_pos) {
+                Some(&existing) if existing != key => {
+                    if (existing - key) % 12 != 0 {
                         panic!("Conflicting key signatures at {:?}", current_pos);
                     }
+                    if !transposed {
+                        self.keys.insert(current_pos, key);
+                    }
+                }
+                Some(_) => (),
+                None => {
+                    self.keys.insert(current_pos, key);
                 }
-                None => (),
             }
         }
 
@@ -275,6 +557,88 @@ impl PhraseList {
         }
     }
 
+    /// Get the phrases in the list.
+    pub fn phrases(&self) -> &[Phrase] {
+        &self.phrases
+    }
+
+    /// Remove phrases which double another phrase at the unison or octave, with the same rhythm. The phrase from the part
+    /// with the highest weight is kept, then the one closest to middle C, and if keep_octaves is set, a doubling an octave
+    /// away from it is merged in as a pianistic octave.
+    pub fn remove_doublings(&mut self, keep_octaves: bool) {
+        let mut groups: BTreeMap<PitchClasses, Vec<Phrase>> = BTreeMap::new();
+        for phrase in self.phrases.drain(..) {
+            groups
+                .entry(phrase.pitch_classes())
+                .or_default()
+                .push(phrase);
+        }
+
+        let parts = &self.parts;
+        let weight = |p: &Phrase| {
+            p.source()
+                .and_then(|s| parts.get(s.part))
+                .map_or(1.0, |part| part.weight)
+        };
+        for (_, mut group) in groups {
+            group.sort_by(|a, b| {
+                weight(b).partial_cmp(&weight(a)).unwrap().then_with(|| {
+                    let distance = |p: &Phrase| (p.mean() as i32 - MIDDLE_C as i32).abs();
+                    distance(a).cmp(&distance(b))
+                })
+            });
+            let mut group = group.into_iter();
+            let mut kept = group.next().unwrap();
+            // Only doublings in other parts are removed, keeping double stops and divisi within the part.
+            let part = kept.source().map(|s| s.part);
+            let (same_part, mut group): (Vec<Phrase>, Vec<Phrase>) =
+                group.partition(|p| part.is_some() && p.source().map(|s| s.part) == part);
+            if keep_octaves {
+                if let Some(index) = group.iter().position(|p| kept.is_octave_of(p)) {
+                    kept.merge(group.swap_remove(index));
+                }
+            }
+            self.phrases.push(kept);
+            self.phrases.extend(same_part);
+        }
+    }
+
+    /// Find the stave a phrase is closest to, based on the maximum and minimum pitches of all phrases at its start position.
+    /// Also returns the distance to each stave.
+    fn closest_stave(&self, phrase: &Phrase, staves: u8) -> (usize, Candidates) {
+        let (first_element, _) = phrase.first();
+        let (sum, total) = first_element.mean();
+        self.closest_stave_at(phrase.start(), (sum / total) as u8, staves)
+    }
+
+    /// Find the stave a given mean pitch is closest to, based on the maximum and minimum pitches of all phrases at a position.
+    /// Also returns the distance to each stave.
+    fn closest_stave_at(&self, position: Fraction, mean: u8, staves: u8) -> (usize, Candidates) {
+        let max = self
+            .phrases
+            .iter()
+            .filter_map(|p| p.max_at(position))
+            .max()
+            .unwrap();
+        let min = self
+            .phrases
+            .iter()
+            .filter_map(|p| p.min_at(position))
+            .min()
+            .unwrap();
+
+        let midpoints = (0..staves).rev().map(|i| {
+            let split_size = (max - min) / (staves + 1);
+            (i + 1) * split_size + min
+        });
+        let distances: Candidates = midpoints
+            .enumerate()
+            .map(|(index, i)| (index, (mean as i32 - i as i32).unsigned_abs()))
+            .collect();
+        let (index, _) = *distances.iter().min_by_key(|(_, d)| *d).unwrap();
+        (index, distances)
+    }
+
     /// Distribute the phrases onto staves.
     pub fn distribute_staves(mut self, staves: u8) -> StaveList {
         self.phrases.sort_unstable_by_key(|a| a.start());
@@ -283,39 +647,138 @@ impl PhraseList {
         // For every phrase, get the maximum and minimum pitches at the start position, and split evenly for each phrase. Then allocate phrase based on which stave it's closest to.
         for phrase in &self.phrases {
             if phrase.num_elements() > 0 {
-                let start = phrase.start();
-                let (first_element, _) = phrase.first();
-                let (sum, total) = first_element.mean();
-                let start_mean = sum / total;
-                let max = self
-                    .phrases
-                    .iter()
-                    .filter_map(|p| p.max_at(start))
-                    .max()
-                    .unwrap();
-                let min = self
-                    .phrases
-                    .iter()
-                    .filter_map(|p| p.min_at(start))
-                    .min()
-                    .unwrap();
+                let (index, candidates) = self.closest_stave(phrase, staves);
+                self.log.record(
+                    "distribute_staves",
+                    describe_phrase(&self.parts, phrase),
+                    phrase.start(),
+                    candidates,
+                    Action::Placed { stave: index },
+                );
+                new_staves[index].push(phrase.clone());
+            }
+        }
 
-                let midpoints = (0..staves).rev().map(|i| {
-                    let split_size = (max - min) / (staves + 1);
-                    (i + 1) * split_size + min
-                });
-                let (index, _) = midpoints
-                    .enumerate()
-                    .min_by_key(|(_, i)| (start_mean as i32 - *i as i32).abs())
-                    .unwrap();
+        StaveList {
+            staves: new_staves,
+            parts: self.parts,
+            keys: self.keys,
+            dropped: Vec::new(),
+            times: self.times,
+            log: self.log,
+        }
+    }
+
+    /// Distribute the phrases onto staves, keeping phrases from the same instrument family which start together on the same stave. Phrases with no known family are distributed as in distribute_staves.
+    pub fn group_families(mut self, staves: u8) -> StaveList {
+        self.phrases.sort_unstable_by_key(|a| a.start());
+        let mut new_staves = vec![Vec::new(); staves as usize];
+
+        let mut groups: BTreeMap<(Fraction, InstrumentFamily), Vec<&Phrase>> = BTreeMap::new();
+        for phrase in &self.phrases {
+            if phrase.num_elements() > 0 {
+                match phrase.family() {
+                    Some(family) => groups
+                        .entry((phrase.start(), family))
+                        .or_default()
+                        .push(phrase),
+                    None => {
+                        let (index, candidates) = self.closest_stave(phrase, staves);
+                        self.log.record(
+                            "group_families",
+                            describe_phrase(&self.parts, phrase),
+                            phrase.start(),
+                            candidates,
+                            Action::Placed { stave: index },
+                        );
+                        new_staves[index].push(phrase.clone());
+                    }
+                }
+            }
+        }
+
+        // Allocate each group based on the mean of the first elements of its phrases.
+        for ((start, _), group) in groups {
+            let (sum, total) = group.iter().fold((0, 0), |(sum, total), phrase| {
+                let (first_element, _) = phrase.first();
+                let (el_sum, el_total) = first_element.mean();
+                (sum + el_sum, total + el_total)
+            });
+            let (index, candidates) = self.closest_stave_at(start, (sum / total) as u8, staves);
+            for phrase in group {
+                self.log.record(
+                    "group_families",
+                    describe_phrase(&self.parts, phrase),
+                    start,
+                    candidates.clone(),
+                    Action::Placed { stave: index },
+                );
                 new_staves[index].push(phrase.clone());
             }
         }
+        // Phrases with no family were placed before the groups, so put each stave back in start order.
+        for stave in &mut new_staves {
+            stave.sort_by_key(|phrase| phrase.start());
+        }
+
+        StaveList {
+            staves: new_staves,
+            parts: self.parts,
+            keys: self.keys,
+            dropped: Vec::new(),
+            times: self.times,
+            log: self.log,
+        }
+    }
+
+    /// Place the phrases onto staves using a user specified mapping of parts to staves. Phrases from parts which aren't in the mapping are distributed as in distribute_staves.
+    pub fn map_staves(mut self, staves: u8, mapping: &PartMapping) -> StaveList {
+        self.phrases.sort_unstable_by_key(|a| a.start());
+        let mut new_staves = vec![Vec::new(); staves as usize];
+
+        for phrase in &self.phrases {
+            if phrase.num_elements() > 0 {
+                let entry = phrase
+                    .source()
+                    .and_then(|source| mapping.get(&self.parts[source.part]));
+                match entry {
+                    Some(entry) => {
+                        let index = (entry.stave - 1) as usize;
+                        self.log.record(
+                            "map_staves",
+                            describe_phrase(&self.parts, phrase),
+                            phrase.start(),
+                            Vec::new(),
+                            Action::Placed { stave: index },
+                        );
+                        let mut phrase = phrase.clone();
+                        if entry.octaves != 0 {
+                            phrase.transpose_octaves(entry.octaves);
+                        }
+                        new_staves[index].push(phrase);
+                    }
+                    None => {
+                        let (index, candidates) = self.closest_stave(phrase, staves);
+                        self.log.record(
+                            "map_staves",
+                            describe_phrase(&self.parts, phrase),
+                            phrase.start(),
+                            candidates,
+                            Action::Placed { stave: index },
+                        );
+                        new_staves[index].push(phrase.clone());
+                    }
+                }
+            }
+        }
 
         StaveList {
             staves: new_staves,
+            parts: self.parts,
             keys: self.keys,
+            dropped: Vec::new(),
             times: self.times,
+            log: self.log,
         }
     }
 
@@ -345,47 +808,256 @@ impl PhraseList {
             let (first_element, _) = phrase.first();
             let (sum, total) = first_element.mean();
             let start_mean = sum / total;
-            let (index, _) = averages
+            let distances: Candidates = averages
                 .iter()
                 .enumerate()
-                .min_by_key(|(_, i)| (start_mean as i32 - **i as i32).abs())
-                .unwrap();
+                .map(|(index, i)| (index, (start_mean as i32 - *i as i32).unsigned_abs()))
+                .collect();
+            let (index, _) = *distances.iter().min_by_key(|(_, d)| *d).unwrap();
+            self.log.record(
+                "merge_by_average",
+                describe_phrase(&self.parts, &phrase),
+                phrase.start(),
+                distances,
+                Action::Placed { stave: index },
+            );
             averages[index] = phrase.mean();
             new_phrases[index][0].merge(phrase);
         }
         StaveList {
             staves: new_phrases,
+            parts: self.parts,
             keys: self.keys,
+            dropped: Vec::new(),
             times: self.times,
+            log: self.log,
+        }
+    }
+}
+
+/// The reason a note was dropped from a stave.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DropReason {
+    /// The note was further from the other notes on the stave than the hand can stretch.
+    Stretch,
+    /// There were more notes sounding at once than the hand has fingers.
+    Fingers,
+}
+
+impl DropReason {
+    /// Get a description of the reason.
+    pub fn description(self) -> &'static str {
+        match self {
+            DropReason::Stretch => "beyond the stretch of the hand",
+            DropReason::Fingers => "more notes than fingers",
         }
     }
 }
 
+/// A note which couldn't be played on its stave or moved to a neighbouring one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedNote {
+    /// The dropped note as a phrase containing just that note, at its original position.
+    pub phrase: Phrase,
+    /// The index of the stave the note was dropped from.
+    pub stave: usize,
+    /// The position at which the note couldn't be played.
+    pub position: Fraction,
+    pub reason: DropReason,
+}
+
 /// Represents a list of staves, with each stave containing a list of phrases.
 #[derive(Debug)]
 pub struct StaveList {
     pub staves: Vec<Vec<Phrase>>,
+    pub parts: Vec<PartInfo>,
     pub keys: BTreeMap<Fraction, i8>,
     pub times: BTreeMap<Fraction, (u8, u8)>,
+    /// Notes dropped while fitting the staves to the hand.
+    pub dropped: Vec<DroppedNote>,
+    /// The decisions made while reducing the score.
+    pub log: DecisionLog,
 }
 
 impl StaveList {
-    /// Check if the phrase can have the phrases based on the largest interval.
-    fn can_have_phrase(stave: &Vec<Phrase>, phrase: &Phrase, largest_stretch: u32) -> bool {
+    /// Get the notes sounding on a stave at a position, along with the index of their phrase and the position of their element.
+    pub fn notes_at(stave: &[Phrase], position: Fraction) -> Vec<(usize, Fraction, Note)> {
+        stave
+            .iter()
+            .enumerate()
+            .filter_map(|(i, phrase)| phrase.element_at(position).map(|el| (i, el)))
+            .flat_map(|(i, (start, element))| element.notes().iter().map(move |n| (i, start, *n)))
+            .collect()
+    }
+
+    /// Rank the notes sounding at a position by importance, returning their indices with the most important first.
+    fn rank_notes(
+        notes: &[(usize, Fraction, Note)],
+        position: Fraction,
+        parts: &[PartInfo],
+        bass_first: bool,
+    ) -> Vec<usize> {
+        let pitches: Vec<u8> = notes.iter().map(|(_, _, n)| n.value()).collect();
+        let onsets: Vec<bool> = notes
+            .iter()
+            .map(|(_, start, n)| *start == position && !n.tie.is_stop())
+            .collect();
+        let weights: Vec<f64> = notes
+            .iter()
+            .map(|(_, _, n)| {
+                n.source
+                    .and_then(|s| parts.get(s.part))
+                    .map_or(1.0, |part| part.weight)
+            })
+            .collect();
+        HandModel::rank(&pitches, &onsets, &weights, bass_first)
+    }
+
+    /// Remove a note from a phrase on a stave, removing the phrase if it becomes empty.
+    fn remove_note(stave: &mut Vec<Phrase>, phrase: usize, start: Fraction, note: Note) {
+        stave[phrase].remove_note(start, note);
+        if stave[phrase].num_elements() == 0 {
+            stave.remove(phrase);
+        }
+    }
+
+    /// Remove a note which can't be played from a phrase on a stave, keeping a record of it in the dropped notes.
+    fn drop_note(
+        stave: &mut Vec<Phrase>,
+        dropped: &mut Vec<DroppedNote>,
+        stave_index: usize,
+        (phrase, start, note): (usize, Fraction, Note),
+        position: Fraction,
+        reason: DropReason,
+    ) {
+        let (_, length) = stave[phrase].elements_ref()[&start];
+        let mut dropped_note = note;
+        dropped_note.cross_staff = None;
+        let mut elements = BTreeMap::new();
+        elements.insert(start, (PhraseElement::Note(dropped_note), length));
+        let mut dropped_phrase = Phrase::new(elements);
+        dropped_phrase.set_family(stave[phrase].family());
+        dropped.push(DroppedNote {
+            phrase: dropped_phrase,
+            stave: stave_index,
+            position,
+            reason,
+        });
+        Self::remove_note(stave, phrase, start, note);
+    }
+
+    /// Get the notes tied together with a note in a phrase, including the note itself, along with their start and length
+    /// in order.
+    fn tied_notes(phrase: &Phrase, start: Fraction, note: Note) -> Vec<(Fraction, Note, Fraction)> {
+        let elements = phrase.elements_ref();
+        let tied = |position: Fraction, element: &PhraseElement, stop: bool| {
+            element
+                .notes()
+                .iter()
+                .find(|n| {
+                    n.pitch_equals(&note)
+                        && if stop {
+                            n.tie.is_stop()
+                        } else {
+                            n.tie.is_start()
+                        }
+                })
+                .map(|n| (position, *n, elements[&position].1))
+        };
+        let mut notes = vec![(start, note, elements[&start].1)];
+        while let Some(&(position, _, _)) = notes.first().filter(|(_, n, _)| n.tie.is_stop()) {
+            let previous = elements
+                .range(..position)
+                .next_back()
+                .filter(|(p, (_, length))| **p + *length == position)
+                .and_then(|(p, (element, _))| tied(*p, element, false));
+            match previous {
+                Some(previous) => notes.insert(0, previous),
+                None => break,
+            }
+        }
+        while let Some(&(position, _, length)) = notes.last().filter(|(_, n, _)| n.tie.is_start()) {
+            let next = elements
+                .range(position..)
+                .nth(1)
+                .filter(|(p, _)| **p == position + length)
+                .and_then(|(p, (element, _))| tied(*p, element, true));
+            match next {
+                Some(next) => notes.push(next),
+                None => break,
+            }
+        }
+        notes
+    }
+
+    /// Try to give the top note of an over-wide chord to the stave above, or the bottom note to the stave below, along
+    /// with any notes tied to it. Returns the index of the stave the note was given to and the notes as a phrase, if
+    /// one was moved.
+    fn split_chord(
+        stave: &mut Vec<Phrase>,
+        previous: Option<&mut Vec<Phrase>>,
+        next: Option<&mut Vec<Phrase>>,
+        stave_index: usize,
+        position: Fraction,
+        hand: &HandModel,
+    ) -> Option<(usize, Phrase)> {
+        let notes = Self::notes_at(stave, position);
+        let max = notes.iter().map(|(_, _, n)| n.value()).max().unwrap();
+        let min = notes.iter().map(|(_, _, n)| n.value()).min().unwrap();
+        let neighbours = [
+            (previous, stave_index.wrapping_sub(1), max),
+            (next, stave_index + 1, min),
+        ];
+        for (neighbour, neighbour_index, value) in IntoIterator::into_iter(neighbours) {
+            let neighbour = match neighbour {
+                Some(neighbour) => neighbour,
+                None => continue,
+            };
+            let chord_note = notes.iter().find(|(phrase, _, n)| {
+                n.value() == value
+                    && matches!(
+                        stave[*phrase].element_at(position),
+                        Some((_, PhraseElement::Chord(_)))
+                    )
+            });
+            if let Some(&(phrase, start, note)) = chord_note {
+                let tied_notes = Self::tied_notes(&stave[phrase], start, note);
+                let mut elements = BTreeMap::new();
+                for &(start, note, length) in &tied_notes {
+                    let mut moved_note = note;
+                    moved_note.cross_staff = note.cross_staff.or(Some(stave_index as u8 + 1));
+                    elements.insert(start, (PhraseElement::Note(moved_note), length));
+                }
+                let mut moved_phrase = Phrase::new(elements);
+                moved_phrase.set_family(stave[phrase].family());
+                if Self::can_have_phrase(neighbour, &moved_phrase, hand) {
+                    // The whole run of tied notes is moved, so there are no ties to remove from the notes left behind.
+                    for (start, note, _) in tied_notes.into_iter().rev() {
+                        let untied = Note {
+                            tie: Tie::None,
+                            ..note
+                        };
+                        Self::remove_note(stave, phrase, start, untied);
+                    }
+                    neighbour.push(moved_phrase.clone());
+                    return Some((neighbour_index, moved_phrase));
+                }
+            }
+        }
+        None
+    }
+
+    /// Check if the stave can have the phrase based on what can be played by one hand.
+    fn can_have_phrase(stave: &[Phrase], phrase: &Phrase, hand: &HandModel) -> bool {
         for &position in phrase.elements_ref().keys() {
-            if let Some(stave_max) = stave
+            let mut pitches: Vec<u8> = Self::notes_at(stave, position)
                 .iter()
-                .filter_map(|phrase| phrase.max_at(position))
-                .max()
-            {
-                let stave_min = stave
-                    .iter()
-                    .filter_map(|phrase| phrase.min_at(position))
-                    .min()
-                    .unwrap();
-                let phrase_max = phrase.max_at(position).unwrap();
-                let phrase_min = phrase.min_at(position).unwrap();
-                if stave_max.max(phrase_max) - stave_min.min(phrase_min) > largest_stretch as u8 {
+                .map(|(_, _, n)| n.value())
+                .collect();
+            if !pitches.is_empty() {
+                let (_, element) = phrase.element_at(position).unwrap();
+                pitches.extend(element.notes().iter().map(|n| n.value()));
+                if !hand.can_play(&pitches) {
                     return false;
                 }
             }
@@ -393,10 +1065,49 @@ impl StaveList {
         true
     }
 
-    /// Transpose the octaves of phrases within each stave to try and be within the given maximum interval.
-    pub fn adjust_octaves(&mut self, largest_stretch: u32) {
+    /// Score a stave and its neighbours by the stretch each would need at a position if it had the phrase, for the
+    /// decision log.
+    fn stretch_candidates(
+        stave_index: usize,
+        stave: &[Phrase],
+        previous: &Option<&mut Vec<Phrase>>,
+        next: &Option<&mut Vec<Phrase>>,
+        phrase: &Phrase,
+        position: Fraction,
+    ) -> Candidates {
+        let stretch = |stave: &[Phrase]| {
+            let mut pitches: Vec<u8> = Self::notes_at(stave, position)
+                .iter()
+                .map(|(_, _, n)| n.value())
+                .collect();
+            if let Some((_, element)) = phrase.element_at(position) {
+                pitches.extend(element.notes().iter().map(|n| n.value()));
+            }
+            match (pitches.iter().min(), pitches.iter().max()) {
+                (Some(min), Some(max)) => (max - min) as u32,
+                _ => 0,
+            }
+        };
+        let mut candidates = Vec::new();
+        if let Some(previous) = previous {
+            candidates.push((stave_index - 1, stretch(previous)));
+        }
+        candidates.push((stave_index, stretch(stave)));
+        if let Some(next) = next {
+            candidates.push((stave_index + 1, stretch(next)));
+        }
+        candidates
+    }
+
+    /// Transpose the octaves of phrases within each stave to try and be within the span of the hand, then make sure the
+    /// hand has enough fingers for every chord. Phrases are moved to neighbouring staves where possible, otherwise the
+    /// least important notes are dropped. If chords are being repaired, the outer notes of a chord too wide for the hand
+    /// are first given to a neighbouring stave.
+    pub fn adjust_octaves(&mut self, hand: &HandModel, split_chords: bool) {
+        let largest_stretch = hand.span;
         let num_staves = self.staves.len();
         for i in 0..num_staves {
+            let bass_first = num_staves > 1 && i == num_staves - 1;
             let (mut previous, stave, mut next) = get_surrounding_mut(&mut self.staves, i);
             stave.sort_unstable_by_key(|a| a.start());
             let positions: Vec<Fraction> = stave
@@ -424,25 +1135,76 @@ impl StaveList {
                         let (total, count) = stave.iter().filter_map(|p| p.mean_at(position)).fold(
                             (0, 0),
                             |(total, count), (el_total, el_count)| {
-                                (total + el_total as u32, count + el_count as u32)
+                                (total + el_total, count + el_count)
                             },
                         );
 
                         let mean = total / count;
                         let midpoint = (min_val + max_val) / 2;
                         if max_val - min_val > largest_stretch {
+                            // Try giving an outer note of a chord to the other hand first.
+                            let split = if split_chords {
+                                Self::split_chord(
+                                    stave,
+                                    previous.as_deref_mut(),
+                                    next.as_deref_mut(),
+                                    i,
+                                    position,
+                                    hand,
+                                )
+                            } else {
+                                None
+                            };
+                            if let Some((to, moved_phrase)) = split {
+                                self.log.record(
+                                    "adjust_octaves",
+                                    describe_phrase(&self.parts, &moved_phrase),
+                                    position,
+                                    Self::stretch_candidates(
+                                        i,
+                                        stave,
+                                        &previous,
+                                        &next,
+                                        &moved_phrase,
+                                        position,
+                                    ),
+                                    Action::Moved {
+                                        from: i,
+                                        to,
+                                        reason: MoveReason::Stretch,
+                                    },
+                                );
+                                continue;
+                            }
+                            let description = describe_phrase(&self.parts, &stave[max_phrase]);
+                            let candidates = Self::stretch_candidates(
+                                i,
+                                stave,
+                                &previous,
+                                &next,
+                                &stave[max_phrase],
+                                position,
+                            );
                             let mut moved = false;
                             if let Some(previous) = &mut previous {
-                                if Self::can_have_phrase(
-                                    previous,
-                                    &stave[max_phrase],
-                                    largest_stretch,
-                                ) {
+                                if Self::can_have_phrase(previous, &stave[max_phrase], hand) {
                                     previous.push(stave.remove(max_phrase));
                                     moved = true;
                                 }
                             }
-                            if !moved {
+                            if moved {
+                                self.log.record(
+                                    "adjust_octaves",
+                                    description,
+                                    position,
+                                    candidates,
+                                    Action::Moved {
+                                        from: i,
+                                        to: i - 1,
+                                        reason: MoveReason::Stretch,
+                                    },
+                                );
+                            } else {
                                 let other_max = stave
                                     .iter()
                                     .enumerate()
@@ -474,18 +1236,93 @@ impl StaveList {
                                     && (i != num_staves - 1 || max_val - 12 >= min_val)
                                 {
                                     stave[max_phrase].transpose_octaves(-1);
+                                    self.log.record(
+                                        "adjust_octaves",
+                                        description,
+                                        position,
+                                        candidates,
+                                        Action::Transposed {
+                                            stave: i,
+                                            octaves: -1,
+                                        },
+                                    );
                                 } else {
+                                    let description =
+                                        describe_phrase(&self.parts, &stave[min_phrase]);
+                                    let candidates = Self::stretch_candidates(
+                                        i,
+                                        stave,
+                                        &previous,
+                                        &next,
+                                        &stave[min_phrase],
+                                        position,
+                                    );
                                     if let Some(next) = &mut next {
                                         next.push(stave.remove(min_phrase));
+                                        self.log.record(
+                                            "adjust_octaves",
+                                            description,
+                                            position,
+                                            candidates,
+                                            Action::Moved {
+                                                from: i,
+                                                to: i + 1,
+                                                reason: MoveReason::Stretch,
+                                            },
+                                        );
                                     } else if (i != num_staves - 1 || min_val + 12 <= other_min)
                                         && stave[min_phrase].max_val() <= 84
                                         && (i != 0 || min_val + 12 <= max_val)
                                     {
                                         stave[min_phrase].transpose_octaves(1);
-                                    } else if i == 0 {
-                                        stave.remove(min_phrase);
+                                        self.log.record(
+                                            "adjust_octaves",
+                                            description,
+                                            position,
+                                            candidates,
+                                            Action::Transposed {
+                                                stave: i,
+                                                octaves: 1,
+                                            },
+                                        );
                                     } else {
-                                        stave.remove(max_phrase);
+                                        // Drop whichever of the outer notes is least important.
+                                        let notes = Self::notes_at(stave, position);
+                                        let ranking = Self::rank_notes(
+                                            &notes,
+                                            position,
+                                            &self.parts,
+                                            bass_first,
+                                        );
+                                        let least = ranking
+                                            .into_iter()
+                                            .rev()
+                                            .find(|&n| {
+                                                let value = notes[n].2.value() as u32;
+                                                value == min_val || value == max_val
+                                            })
+                                            .unwrap();
+                                        let phrase = &stave[notes[least].0];
+                                        self.log.record(
+                                            "adjust_octaves",
+                                            describe_phrase(&self.parts, phrase),
+                                            position,
+                                            Self::stretch_candidates(
+                                                i, stave, &previous, &next, phrase, position,
+                                            ),
+                                            Action::Removed {
+                                                stave: i,
+                                                reason: DropReason::Stretch,
+                                            },
+                                        );
+                                        Self::drop_note(
+                                            stave,
+                                            &mut self.dropped,
+                                            i,
+                                            notes[least],
+                                            position,
+                                            DropReason::Stretch,
+                                        );
                                     }
                                 }
                             }
@@ -496,10 +1333,226 @@ impl StaveList {
                         break;
                     }
                 }
+
+                // Make sure there are enough fingers, moving or dropping the least important notes.
+                loop {
+                    let notes = Self::notes_at(stave, position);
+                    let pitches: Vec<u8> = notes.iter().map(|(_, _, n)| n.value()).collect();
+                    if hand.can_play(&pitches) {
+                        break;
+                    }
+                    let ranking = Self::rank_notes(&notes, position, &self.parts, bass_first);
+                    let least = notes[*ranking.last().unwrap()];
+                    let phrase = least.0;
+                    let description = describe_phrase(&self.parts, &stave[phrase]);
+                    let candidates = Self::stretch_candidates(
+                        i,
+                        stave,
+                        &previous,
+                        &next,
+                        &stave[phrase],
+                        position,
+                    );
+                    let action = if let Some(previous) = previous
+                        .as_mut()
+                        .filter(|previous| Self::can_have_phrase(previous, &stave[phrase], hand))
+                    {
+                        previous.push(stave.remove(phrase));
+                        Action::Moved {
+                            from: i,
+                            to: i - 1,
+                            reason: MoveReason::Fingers,
+                        }
+                    } else if let Some(next) = next
+                        .as_mut()
+                        .filter(|next| Self::can_have_phrase(next, &stave[phrase], hand))
+                    {
+                        next.push(stave.remove(phrase));
+                        Action::Moved {
+                            from: i,
+                            to: i + 1,
+                            reason: MoveReason::Fingers,
+                        }
+                    } else {
+                        Self::drop_note(
+                            stave,
+                            &mut self.dropped,
+                            i,
+                            least,
+                            position,
+                            DropReason::Fingers,
+                        );
+                        Action::Removed {
+                            stave: i,
+                            reason: DropReason::Fingers,
+                        }
+                    };
+                    self.log
+                        .record("adjust_octaves", description, position, candidates, action);
+                }
             }
         }
     }
 
+    /// Give the outer notes of chords wider than the span of the hand to the hand of a neighbouring stave, wherever it
+    /// has room for them.
+    pub fn repair_chords(&mut self, hand: &HandModel) {
+        let num_staves = self.staves.len();
+        for i in 0..num_staves {
+            let (mut previous, stave, mut next) = get_surrounding_mut(&mut self.staves, i);
+            let positions: Vec<Fraction> = stave
+                .iter()
+                .map(|p| p.elements_ref().keys().cloned())
+                .kmerge()
+                .unique()
+                .collect();
+
+            for position in positions {
+                loop {
+                    let pitches: Vec<u8> = Self::notes_at(stave, position)
+                        .iter()
+                        .map(|(_, _, n)| n.value())
+                        .collect();
+                    let stretch = match (pitches.iter().min(), pitches.iter().max()) {
+                        (Some(min), Some(max)) => (max - min) as u32,
+                        _ => break,
+                    };
+                    if stretch <= hand.span {
+                        break;
+                    }
+                    match Self::split_chord(
+                        stave,
+                        previous.as_deref_mut(),
+                        next.as_deref_mut(),
+                        i,
+                        position,
+                        hand,
+                    ) {
+                        Some((to, moved_phrase)) => self.log.record(
+                            "repair_chords",
+                            describe_phrase(&self.parts, &moved_phrase),
+                            position,
+                            Self::stretch_candidates(
+                                i,
+                                stave,
+                                &previous,
+                                &next,
+                                &moved_phrase,
+                                position,
+                            ),
+                            Action::Moved {
+                                from: i,
+                                to,
+                                reason: MoveReason::Stretch,
+                            },
+                        ),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Describe where a note came from in the source score.
+    pub fn describe_source(&self, source: &Source) -> String {
+        source_description(&self.parts, source)
+    }
+
+    /// List every note on every stave along with where it came from in the source score.
+    pub fn source_listing(&self) -> String {
+        let mut listing = String::new();
+        for (stave_num, stave) in self.staves.iter().enumerate() {
+            let notes = stave
+                .iter()
+                .flat_map(|phrase| phrase.elements_ref())
+                .flat_map(|(position, (element, _))| {
+                    element.notes().iter().map(move |note| (*position, note))
+                })
+                .sorted_by_key(|(position, _)| *position);
+            for (position, note) in notes {
+                let source = match &note.source {
+                    Some(source) => self.describe_source(source),
+                    None => "unknown".to_string(),
+                };
+                listing.push_str(&format!(
+                    "stave {}, position {}: {} from {}\n",
+                    stave_num + 1,
+                    position,
+                    note,
+                    source
+                ));
+            }
+        }
+        for dropped in &self.dropped {
+            let (start, (element, _)) = dropped.phrase.elements_ref().iter().next().unwrap();
+            let note = &element.notes()[0];
+            let source = match &note.source {
+                Some(source) => self.describe_source(source),
+                None => "unknown".to_string(),
+            };
+            listing.push_str(&format!(
+                "dropped from stave {}, position {}: {} from {}, {}\n",
+                dropped.stave + 1,
+                start,
+                note,
+                source,
+                dropped.reason.description()
+            ));
+        }
+        listing
+    }
+
+    /// Add an extra ossia stave above the others containing the notes which were dropped, unless none were.
+    pub fn add_ossia_stave(&mut self) {
+        if self.dropped.is_empty() {
+            return;
+        }
+        let mut ossia = Phrase::default();
+        for dropped in &self.dropped {
+            ossia.merge(dropped.phrase.clone());
+        }
+        for stave in &mut self.staves {
+            for phrase in stave {
+                phrase.shift_cross_staff(1);
+            }
+        }
+        self.staves.insert(0, vec![ossia]);
+    }
+
+    /// Merge the phrases on each stave into up to two voices, the upper of which comes first. A phrase is merged into
+    /// a voice as chords where their rhythms match, and otherwise starts the second voice.
+    ///
+    /// Only two voices are kept, so once there are two, a phrase whose rhythm matches neither is merged into the voice
+    /// closest in pitch in the same way as `merge`, splitting notes into tied notes where the rhythms differ. This is a
+    /// deliberate limit to keep each stave playable by one hand.
+    pub fn merge_voices(self) -> Self {
+        let mut new_staves = Vec::new();
+        for mut stave in self.staves {
+            stave.sort_by_cached_key(|p| std::cmp::Reverse(p.mean()));
+            let mut voices: Vec<Phrase> = Vec::new();
+            for phrase in stave {
+                if let Some(voice) = voices.iter().position(|v| v.rhythm_matches(&phrase)) {
+                    voices[voice].merge(phrase);
+                } else if voices.len() < 2 {
+                    voices.push(phrase);
+                } else {
+                    let mean = phrase.mean() as i32;
+                    let voice_means: Vec<i32> = voices.iter().map(|v| v.mean() as i32).collect();
+                    let voice = (0..voices.len())
+                        .min_by_key(|&i| (voice_means[i] - mean).abs())
+                        .unwrap();
+                    voices[voice].merge(phrase);
+                }
+            }
+            voices.sort_by_cached_key(|v| std::cmp::Reverse(v.mean()));
+            new_staves.push(voices);
+        }
+        Self {
+            staves: new_staves,
+            ..self
+        }
+    }
+
     /// Merge all phrases on a stave together.
     pub fn merge(self) -> Self {
         let mut new_staves = Vec::new();
@@ -513,11 +1566,40 @@ impl StaveList {
         Self {
             times: self.times,
             keys: self.keys,
+            parts: self.parts,
             staves: new_staves,
+            dropped: self.dropped,
+            log: self.log,
         }
     }
 }
 
+/// Describe where a note came from in the source score.
+fn source_description(parts: &[PartInfo], source: &Source) -> String {
+    let part = &parts[source.part];
+    let name = if part.name.is_empty() {
+        part.id.clone()
+    } else {
+        format!("{} ({})", part.name, part.id)
+    };
+    format!("{}, bar {}, voice {}", name, source.measure, source.voice)
+}
+
+/// Describe a phrase by where it came from in the source score and the notes it starts with.
+fn describe_phrase(parts: &[PartInfo], phrase: &Phrase) -> String {
+    let source = match phrase.source() {
+        Some(source) => source_description(parts, &source),
+        None => "unknown".to_string(),
+    };
+    let (first_element, _) = phrase.first();
+    let notes = first_element
+        .notes()
+        .iter()
+        .map(|n| n.to_string())
+        .join("/");
+    format!("{}, starting {}", source, notes)
+}
+
 /// Contains information about instrument transposition.
 struct Transpose {
     chromatic: i32,
@@ -568,8 +1650,417 @@ fn get_surrounding_mut<T>(slice: &mut [T], i: usize) -> (Option<&mut T>, &mut T,
 
 #[cfg(test)]
 mod tests {
-    use crate::phrase_element::{Note, NoteName, Tie};
-    use crate::score_representation::Transpose;
+    use crate::explain::{Action, DecisionLog};
+    use crate::fraction::Fraction;
+    use crate::hand_model::HandModel;
+    use crate::instrument::InstrumentFamily;
+    use crate::phrase::Phrase;
+    use crate::phrase_element::{Note, NoteName, PhraseElement, Source, Tie};
+    use crate::score_representation::{DropReason, DroppedNote, PhraseList, StaveList, Transpose};
+    use std::collections::BTreeMap;
+
+    fn phrase(first: NoteName, second: NoteName, octave: u8) -> Phrase {
+        let mut elements = BTreeMap::new();
+        for (i, step) in [first, second].iter().enumerate() {
+            elements.insert(
+                Fraction::new(i as i32, 1),
+                (
+                    PhraseElement::Note(Note::new(*step, octave, 0, Tie::None)),
+                    Fraction::new(1, 1),
+                ),
+            );
+        }
+        Phrase::new(elements)
+    }
+
+    fn doubled_phrases() -> PhraseList {
+        let mut phrase_list = PhraseList::new();
+        phrase_list.phrases = vec![
+            phrase(NoteName::C, NoteName::E, 5),
+            phrase(NoteName::C, NoteName::E, 4),
+            phrase(NoteName::C, NoteName::E, 4),
+            phrase(NoteName::C, NoteName::E, 3),
+            phrase(NoteName::D, NoteName::F, 4),
+        ];
+        phrase_list
+    }
+
+    #[test]
+    fn excerpt() {
+        // A note tied over the start of the excerpt and a note tied over its end.
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::new(3, 1),
+            (
+                PhraseElement::Note(Note::new(NoteName::C, 4, 0, Tie::Start)),
+                Fraction::new(1, 1),
+            ),
+        );
+        elements.insert(
+            Fraction::new(4, 1),
+            (
+                PhraseElement::Note(Note::new(NoteName::C, 4, 0, Tie::Stop)),
+                Fraction::new(1, 1),
+            ),
+        );
+        elements.insert(
+            Fraction::new(7, 1),
+            (
+                PhraseElement::Note(Note::new(NoteName::D, 4, 0, Tie::Start)),
+                Fraction::new(1, 1),
+            ),
+        );
+        let mut phrase_list = PhraseList::new();
+        phrase_list.phrases = vec![Phrase::new(elements)];
+        phrase_list.keys.insert(Fraction::zero(), -3);
+        phrase_list.times.insert(Fraction::zero(), (4, 4));
+        phrase_list.times.insert(Fraction::new(4, 1), (3, 4));
+        phrase_list.times.insert(Fraction::new(8, 1), (2, 4));
+        phrase_list.excerpt(Fraction::new(4, 1), Fraction::new(8, 1));
+
+        let elements = phrase_list.phrases[0].elements_ref();
+        assert_eq!(
+            elements[&Fraction::zero()].0,
+            PhraseElement::Note(Note::new(NoteName::C, 4, 0, Tie::None))
+        );
+        assert_eq!(
+            elements[&Fraction::new(3, 1)].0,
+            PhraseElement::Note(Note::new(NoteName::D, 4, 0, Tie::None))
+        );
+        assert_eq!(phrase_list.keys.get(&Fraction::zero()), Some(&-3));
+        assert_eq!(
+            phrase_list.times.into_iter().collect::<Vec<_>>(),
+            vec![(Fraction::zero(), (3, 4))]
+        );
+    }
+
+    #[test]
+    fn remove_doublings() {
+        let mut phrase_list = doubled_phrases();
+        phrase_list.remove_doublings(false);
+        assert_eq!(phrase_list.phrases.len(), 2);
+        assert!(phrase_list
+            .phrases
+            .contains(&phrase(NoteName::C, NoteName::E, 4)));
+        assert!(phrase_list
+            .phrases
+            .contains(&phrase(NoteName::D, NoteName::F, 4)));
+    }
+
+    #[test]
+    fn remove_doublings_same_part() {
+        let from_part = |phrase: Phrase, part| {
+            let mut elements = phrase.elements();
+            for (element, _) in elements.values_mut() {
+                for note in element.notes_mut() {
+                    note.source = Some(Source {
+                        part,
+                        measure: 1,
+                        voice: 1,
+                    });
+                }
+            }
+            Phrase::new(elements)
+        };
+        // One part playing in octaves, doubled by another part.
+        let mut phrase_list = PhraseList::new();
+        phrase_list.phrases = vec![
+            from_part(phrase(NoteName::C, NoteName::E, 4), 0),
+            from_part(phrase(NoteName::C, NoteName::E, 5), 0),
+            from_part(phrase(NoteName::C, NoteName::E, 4), 1),
+        ];
+        phrase_list.remove_doublings(false);
+        assert_eq!(
+            phrase_list.phrases,
+            vec![
+                from_part(phrase(NoteName::C, NoteName::E, 4), 0),
+                from_part(phrase(NoteName::C, NoteName::E, 5), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_doublings_keep_octaves() {
+        let mut phrase_list = doubled_phrases();
+        phrase_list.remove_doublings(true);
+        assert_eq!(phrase_list.phrases.len(), 2);
+
+        let mut expected = phrase(NoteName::C, NoteName::E, 4);
+        expected.merge(phrase(NoteName::C, NoteName::E, 3));
+        assert!(phrase_list.phrases.contains(&expected));
+    }
+
+    #[test]
+    fn group_families_order() {
+        // A woodwind phrase, and a later phrase with no family which is placed before the family groups.
+        let mut woodwind = phrase(NoteName::C, NoteName::E, 4);
+        woodwind.set_family(Some(InstrumentFamily::Woodwind));
+        let mut later = phrase(NoteName::G, NoteName::A, 4);
+        later.offset(Fraction::new(2, 1));
+        let mut phrase_list = PhraseList::new();
+        phrase_list.phrases = vec![later.clone(), woodwind.clone()];
+
+        let stave_list = phrase_list.group_families(1);
+        assert_eq!(stave_list.staves, vec![vec![woodwind, later]]);
+    }
+
+    #[test]
+    fn split_chord() {
+        let c = Note::new(NoteName::C, 4, 0, Tie::None);
+        let g = Note::new(NoteName::G, 5, 0, Tie::None);
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Chord(vec![c, g]), Fraction::new(1, 1)),
+        );
+        let mut stave_list = StaveList {
+            staves: vec![vec![Phrase::new(elements)], Vec::new()],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times: BTreeMap::new(),
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+
+        // Without repairing chords no note is split off to be displayed on another stave.
+        let element_phrase = |element| {
+            let mut elements = BTreeMap::new();
+            elements.insert(Fraction::zero(), (element, Fraction::new(1, 1)));
+            Phrase::new(elements)
+        };
+        let e = Note::new(NoteName::E, 4, 0, Tie::None);
+        let mut unrepaired = StaveList {
+            staves: vec![
+                vec![
+                    element_phrase(PhraseElement::Chord(vec![c, e])),
+                    element_phrase(PhraseElement::Note(g)),
+                ],
+                Vec::new(),
+            ],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times: BTreeMap::new(),
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+        unrepaired.adjust_octaves(&HandModel::new(12, 5), false);
+        assert!(unrepaired
+            .staves
+            .iter()
+            .flatten()
+            .flat_map(|phrase| phrase.elements_ref().values())
+            .all(|(element, _)| element.notes().iter().all(|n| n.cross_staff.is_none())));
+
+        stave_list.adjust_octaves(&HandModel::new(12, 5), true);
+
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Note(g), Fraction::new(1, 1)),
+        );
+        assert_eq!(stave_list.staves[0], vec![Phrase::new(elements)]);
+
+        let mut moved = c;
+        moved.cross_staff = Some(1);
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (PhraseElement::Note(moved), Fraction::new(1, 1)),
+        );
+        assert_eq!(stave_list.staves[1], vec![Phrase::new(elements)]);
+    }
+
+    #[test]
+    fn split_tied_chord() {
+        // A chord tied over into a second beat, whose bottom note is moved as a whole.
+        let tied = |name, octave, tie| Note::new(name, octave, 0, tie);
+        let mut elements = BTreeMap::new();
+        elements.insert(
+            Fraction::zero(),
+            (
+                PhraseElement::Chord(vec![
+                    tied(NoteName::C, 4, Tie::Start),
+                    tied(NoteName::G, 5, Tie::Start),
+                ]),
+                Fraction::new(1, 1),
+            ),
+        );
+        elements.insert(
+            Fraction::new(1, 1),
+            (
+                PhraseElement::Chord(vec![
+                    tied(NoteName::C, 4, Tie::Stop),
+                    tied(NoteName::G, 5, Tie::Stop),
+                ]),
+                Fraction::new(1, 1),
+            ),
+        );
+        let mut stave_list = StaveList {
+            staves: vec![vec![Phrase::new(elements)], Vec::new()],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times: BTreeMap::new(),
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+        stave_list.adjust_octaves(&HandModel::new(12, 5), true);
+
+        let chain = |name, octave, cross_staff| {
+            let mut elements = BTreeMap::new();
+            for (position, tie) in [(0, Tie::Start), (1, Tie::Stop)] {
+                let mut note = tied(name, octave, tie);
+                note.cross_staff = cross_staff;
+                elements.insert(
+                    Fraction::new(position, 1),
+                    (PhraseElement::Note(note), Fraction::new(1, 1)),
+                );
+            }
+            Phrase::new(elements)
+        };
+        assert_eq!(stave_list.staves[0], vec![chain(NoteName::G, 5, None)]);
+        assert_eq!(stave_list.staves[1], vec![chain(NoteName::C, 4, Some(1))]);
+    }
+
+    #[test]
+    fn dropped_notes() {
+        let note_phrase = |name| {
+            let mut elements = BTreeMap::new();
+            elements.insert(
+                Fraction::zero(),
+                (
+                    PhraseElement::Note(Note::new(name, 4, 0, Tie::None)),
+                    Fraction::new(1, 1),
+                ),
+            );
+            Phrase::new(elements)
+        };
+        let mut stave_list = StaveList {
+            staves: vec![vec![
+                note_phrase(NoteName::C),
+                note_phrase(NoteName::E),
+                note_phrase(NoteName::G),
+            ]],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times: BTreeMap::new(),
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        };
+        stave_list.adjust_octaves(&HandModel::new(12, 2), false);
+
+        // The middle note is the least important, and there is no other stave to move it to.
+        assert_eq!(stave_list.staves[0].len(), 2);
+        assert_eq!(
+            stave_list.dropped,
+            vec![DroppedNote {
+                phrase: note_phrase(NoteName::E),
+                stave: 0,
+                position: Fraction::zero(),
+                reason: DropReason::Fingers,
+            }]
+        );
+        assert_eq!(
+            stave_list
+                .log
+                .decisions
+                .iter()
+                .map(|d| &d.action)
+                .collect::<Vec<_>>(),
+            vec![&Action::Removed {
+                stave: 0,
+                reason: DropReason::Fingers,
+            }]
+        );
+
+        stave_list.add_ossia_stave();
+        assert_eq!(stave_list.staves.len(), 2);
+        assert_eq!(stave_list.staves[0], vec![note_phrase(NoteName::E)]);
+
+        // No ossia stave is added when nothing was dropped.
+        stave_list.dropped.clear();
+        stave_list.add_ossia_stave();
+        assert_eq!(stave_list.staves.len(), 2);
+    }
+
+    #[test]
+    fn merge_voices() {
+        let quarters = |name, octave| {
+            let mut elements = BTreeMap::new();
+            for i in 0..2 {
+                elements.insert(
+                    Fraction::new(i, 1),
+                    (
+                        PhraseElement::Note(Note::new(name, octave, 0, Tie::None)),
+                        Fraction::new(1, 1),
+                    ),
+                );
+            }
+            Phrase::new(elements)
+        };
+        let mut half = BTreeMap::new();
+        half.insert(
+            Fraction::zero(),
+            (
+                PhraseElement::Note(Note::new(NoteName::C, 3, 0, Tie::None)),
+                Fraction::new(2, 1),
+            ),
+        );
+        let stave_list = StaveList {
+            staves: vec![vec![
+                Phrase::new(half),
+                quarters(NoteName::E, 4),
+                quarters(NoteName::G, 4),
+            ]],
+            parts: Vec::new(),
+            keys: BTreeMap::new(),
+            times: BTreeMap::new(),
+            dropped: Vec::new(),
+            log: DecisionLog::default(),
+        }
+        .merge_voices();
+
+        // The quarters share a rhythm and are merged into chords above the half note.
+        let voices = &stave_list.staves[0];
+        assert_eq!(voices.len(), 2);
+        let mut chords = quarters(NoteName::G, 4);
+        chords.merge(quarters(NoteName::E, 4));
+        assert_eq!(voices[0], chords);
+        assert_eq!(voices[1].elements_ref().len(), 1);
+    }
+
+    #[test]
+    fn key_signatures() {
+        let parse_key = |phrase_list: &mut PhraseList, fifths: i32, chromatic: i32| {
+            let xml = format!(
+                "<attributes><key><fifths>{}</fifths></key></attributes>",
+                fifths
+            );
+            let doc = roxmltree::Document::parse(&xml).unwrap();
+            let mut transpose = Transpose::new();
+            transpose.chromatic = chromatic;
+            phrase_list.parse_attributes(
+                &doc.root_element(),
+                Fraction::zero(),
+                &mut 1,
+                &mut transpose,
+            );
+            phrase_list.keys[&Fraction::zero()]
+        };
+
+        // Untransposed parts keep their key however many sharps or flats it has.
+        for &fifths in &[6, 7, -6, -7] {
+            assert_eq!(parse_key(&mut PhraseList::new(), fifths, 0), fifths as i8);
+        }
+
+        // A clarinet in B flat written in B major sounds in A major, and one written in A flat major sounds in G flat
+        // major, unless a concert part spells it as F sharp major.
+        assert_eq!(parse_key(&mut PhraseList::new(), 5, -2), 3);
+        assert_eq!(parse_key(&mut PhraseList::new(), -4, -2), -6);
+        let mut phrase_list = PhraseList::new();
+        parse_key(&mut phrase_list, -4, -2);
+        assert_eq!(parse_key(&mut phrase_list, 6, 0), 6);
+        assert_eq!(parse_key(&mut phrase_list, -4, -2), 6);
+    }
 
     #[test]
     fn transpose_ordinary() {
@@ -0,0 +1,121 @@
+use crate::phrase_element::{Note, NoteName, Tie};
+use std::cmp::Ordering;
+
+/// The position of each step, numbered from C, on the line of fifths.
+const FIFTHS: [i32; 7] = [0, 2, 4, -1, 1, 3, 5];
+/// The furthest a spelling may lie outside the key on the line of fifths before the note is respelt.
+const MAX_DISTANCE: i32 = 5;
+/// The cost of spelling a note against the direction of the melody, as a sharp when falling or a flat when rising.
+const AGAINST_DIRECTION: i32 = 5;
+
+/// Choose how to spell a note in a key, returning its step, alteration and octave.
+///
+/// A note keeps its spelling unless it has a double sharp or flat, or lies too far outside the key on the line of
+/// fifths, such as E sharp in C major. Otherwise the spellings are scored by how far they lie outside the key, so that
+/// notes of the key are spelt as they are in the key, and other notes as the nearest sharp or flat to the key,
+/// preferring sharps where the melody rises through them and flats where it falls.
+pub fn spell(note: &Note, key: i8, direction: Ordering) -> (NoteName, i8, u8) {
+    let key = key as i32;
+    // How far a position on the line of fifths lies on the flat and sharp sides of the key.
+    let outside = |fifths: i32| (key - 1 - fifths, fifths - key - 5);
+    let (flat_side, sharp_side) = outside(FIFTHS[note.step as usize] + 7 * note.alter as i32);
+    if note.alter.abs() <= 1 && flat_side.max(sharp_side) <= MAX_DISTANCE {
+        return (note.step, note.alter, note.octave);
+    }
+
+    let value = note.value() as i32;
+    (0..7)
+        .filter_map(|index| {
+            let step = NoteName::from_index(index).unwrap();
+            let base = Note::new(step, 0, 0, Tie::None).value() as i32;
+            let alter = (value - base + 6).rem_euclid(12) - 6;
+            if alter.abs() > 2 {
+                return None;
+            }
+            let (flat_side, sharp_side) = outside(FIFTHS[index as usize] + 7 * alter);
+            let mut cost = 2 * flat_side.max(sharp_side).max(0);
+            if (flat_side > 0 && direction == Ordering::Greater)
+                || (sharp_side > 0 && direction == Ordering::Less)
+            {
+                cost += AGAINST_DIRECTION;
+            }
+            let octave = (value - base - alter) / 12;
+            Some(((cost, alter.abs()), (step, alter as i8, octave as u8)))
+        })
+        .min_by_key(|(cost, _)| *cost)
+        .map(|(_, spelling)| spelling)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::phrase_element::{Note, NoteName, Tie};
+    use crate::spelling;
+    use std::cmp::Ordering;
+
+    fn spell(
+        step: NoteName,
+        alter: i8,
+        octave: u8,
+        key: i8,
+        direction: Ordering,
+    ) -> (NoteName, i8, u8) {
+        spelling::spell(&Note::new(step, octave, alter, Tie::None), key, direction)
+    }
+
+    #[test]
+    fn keep_spelling() {
+        // Chromatic notes near the key keep their spelling whichever way the melody goes.
+        assert_eq!(
+            spell(NoteName::F, 1, 4, -3, Ordering::Less),
+            (NoteName::F, 1, 4)
+        );
+        assert_eq!(
+            spell(NoteName::C, -1, 5, -3, Ordering::Greater),
+            (NoteName::C, -1, 5)
+        );
+        assert_eq!(
+            spell(NoteName::A, 1, 4, 0, Ordering::Equal),
+            (NoteName::A, 1, 4)
+        );
+    }
+
+    #[test]
+    fn key_notes() {
+        // F rather than E sharp or G double flat in C major.
+        assert_eq!(
+            spell(NoteName::E, 1, 4, 0, Ordering::Greater),
+            (NoteName::F, 0, 4)
+        );
+        assert_eq!(
+            spell(NoteName::G, -2, 4, 0, Ordering::Less),
+            (NoteName::F, 0, 4)
+        );
+        // F sharp rather than G flat in D major, and C flat rather than B in G flat major.
+        assert_eq!(
+            spell(NoteName::G, -1, 4, 2, Ordering::Less),
+            (NoteName::F, 1, 4)
+        );
+        assert_eq!(
+            spell(NoteName::B, 0, 4, -6, Ordering::Equal),
+            (NoteName::C, -1, 5)
+        );
+    }
+
+    #[test]
+    fn chromatic_notes() {
+        // A respelt chromatic note is sharpened when rising and flattened when falling.
+        assert_eq!(
+            spell(NoteName::B, 2, 3, 0, Ordering::Greater),
+            (NoteName::C, 1, 4)
+        );
+        assert_eq!(
+            spell(NoteName::B, 2, 3, 0, Ordering::Less),
+            (NoteName::D, -1, 4)
+        );
+        assert_eq!(
+            spell(NoteName::F, -2, 4, 0, Ordering::Equal),
+            (NoteName::E, -1, 4)
+        );
+    }
+}